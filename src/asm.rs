@@ -0,0 +1,430 @@
+//! A small assembler, using the same mnemonics that `Instruction` displays
+//! (and that the disassembler prints).
+//!
+//! ```text
+//! ; Comments start with a semicolon.
+//!         LD I, sprite        ; Labels can be used anywhere an address can.
+//!         LD V0, 10
+//!         DRW V0, V0, 3
+//! loop:   JP loop
+//!
+//! :org 0x300                  ; Continue assembling at this address.
+//! sprite: :byte 0b01000000, 0b11100000, 0b01000000
+//!         :word 0x1234, loop  ; Big endian 16-bit values.
+//! ```
+//!
+//! Mnemonics and register names are case-insensitive. Numbers can be
+//! decimal, hex (`0x`), or binary (`0b`).
+
+use crate::cpu::{instruction::Instruction, mem::Mem};
+use crate::ParseError;
+use std::collections::HashMap;
+
+/// Assemble a program into a ROM, to be loaded at `Mem::ROM_START`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, ParseError> {
+    // First pass: parse everything, and work out the address of each label.
+    let mut labels = HashMap::new();
+    let mut statements = vec![];
+    let mut addr = Mem::ROM_START as usize;
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let err = |message| ParseError { line, message };
+
+        let mut text = text.split(';').next().unwrap().trim();
+        while let Some((label, rest)) = split_label(text) {
+            // References to it would be read as the register or keyword.
+            if !matches!(parse_operand(label), Ok(Operand::Value(_))) {
+                return Err(err(format!("{label:?} is reserved, and can't be a label")));
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(err(format!("duplicate label {label:?}")));
+            }
+            text = rest.trim_start();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(text).map_err(err)?;
+        if let Statement::Org(org) = statement {
+            if org < Mem::ROM_START as usize {
+                return Err(err(format!(":org 0x{org:x} is before the ROM start")));
+            }
+            addr = org;
+            continue;
+        }
+
+        let size = statement.size();
+        statements.push((line, addr, statement));
+        addr += size;
+    }
+
+    // Second pass: now that all labels are known, encode everything.
+    let mut rom = Rom::default();
+    for (line, addr, statement) in statements {
+        let bytes = statement
+            .encode(&labels)
+            .map_err(|message| ParseError { line, message })?;
+        rom.write(addr, &bytes)
+            .map_err(|message| ParseError { line, message })?;
+    }
+
+    Ok(rom.bytes)
+}
+
+/// If the line starts with `label:`, split that off.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    is_identifier(label).then_some((label, rest))
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The assembled output, starting at `Mem::ROM_START`.
+#[derive(Default)]
+struct Rom {
+    bytes: Vec<u8>,
+    /// Which bytes have been written so far, to catch overlapping `:org`s.
+    written: Vec<bool>,
+}
+
+impl Rom {
+    fn write(&mut self, addr: usize, bytes: &[u8]) -> Result<(), String> {
+        let end = addr + bytes.len();
+        if end > Mem::XO_CHIP_LEN {
+            return Err(format!("address 0x{:x} is past the end of memory", end - 1));
+        }
+
+        let start = addr - Mem::ROM_START as usize;
+        let end = start + bytes.len();
+        if self.bytes.len() < end {
+            self.bytes.resize(end, 0);
+            self.written.resize(end, false);
+        }
+
+        if self.written[start..end].contains(&true) {
+            return Err(format!("overlaps earlier output at 0x{addr:03x}"));
+        }
+        self.bytes[start..end].copy_from_slice(bytes);
+        self.written[start..end].fill(true);
+        Ok(())
+    }
+}
+
+enum Statement {
+    /// `:org addr`
+    Org(usize),
+    /// `:byte value, ...`
+    Bytes(Vec<Value>),
+    /// `:word value, ...`
+    Words(Vec<Value>),
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+}
+
+impl Statement {
+    /// How many bytes this will assemble to.
+    fn size(&self) -> usize {
+        match self {
+            Statement::Org(_) => 0,
+            Statement::Bytes(values) => values.len(),
+            Statement::Words(values) => values.len() * 2,
+            Statement::Instruction { operands, .. } => {
+                if operands.iter().any(|op| matches!(op, Operand::Long(_))) {
+                    4
+                } else {
+                    2
+                }
+            }
+        }
+    }
+
+    fn encode(&self, labels: &HashMap<String, usize>) -> Result<Vec<u8>, String> {
+        match self {
+            Statement::Org(_) => Ok(vec![]),
+            Statement::Bytes(values) => values
+                .iter()
+                .map(|v| {
+                    // Allow negative numbers, as two's complement.
+                    let n = v.resolve(labels)?;
+                    check_range(n, -0x80, 0xff, "byte").map(|n| n as u8)
+                })
+                .collect(),
+            Statement::Words(values) => {
+                let mut bytes = vec![];
+                for v in values {
+                    let n = check_range(v.resolve(labels)?, 0, 0xffff, "word")?;
+                    bytes.extend((n as u16).to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            Statement::Instruction { mnemonic, operands } => {
+                let instr = encode_instruction(mnemonic, operands, labels)?;
+                instr
+                    .to_bytes()
+                    .ok_or_else(|| format!("{instr}: operand out of range"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(i64),
+    Label(String),
+}
+
+impl Value {
+    fn resolve(&self, labels: &HashMap<String, usize>) -> Result<i64, String> {
+        match self {
+            Value::Number(n) => Ok(*n),
+            Value::Label(name) => labels
+                .get(name)
+                .map(|&addr| addr as i64)
+                .ok_or_else(|| format!("unknown label {name:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    /// `V0` through `VF`.
+    V(u8),
+    I,
+    /// `[I]`
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    /// `long addr`, for XO-CHIP's 16-bit `LD I`.
+    Long(Value),
+    Value(Value),
+}
+
+fn parse_statement(text: &str) -> Result<Statement, String> {
+    let (head, rest) = match text.split_once(char::is_whitespace) {
+        Some((head, rest)) => (head, rest.trim()),
+        None => (text, ""),
+    };
+
+    let args = || -> Result<Vec<Value>, String> {
+        if rest.is_empty() {
+            return Err(format!("{head}: missing value"));
+        }
+        rest.split(',').map(|arg| parse_value(arg.trim())).collect()
+    };
+
+    let statement = match head {
+        ":org" => match parse_value(rest)? {
+            Value::Number(n) => Statement::Org(check_range(n, 0, 0xffff, "address")? as usize),
+            Value::Label(_) => return Err(":org: expected a number".to_string()),
+        },
+        ":byte" => Statement::Bytes(args()?),
+        ":word" => Statement::Words(args()?),
+        _ if head.starts_with(':') => return Err(format!("unknown directive {head:?}")),
+        _ => {
+            let operands = if rest.is_empty() {
+                vec![]
+            } else {
+                rest.split(',')
+                    .map(|op| parse_operand(op.trim()))
+                    .collect::<Result<_, _>>()?
+            };
+            Statement::Instruction {
+                mnemonic: head.to_ascii_uppercase(),
+                operands,
+            }
+        }
+    };
+    Ok(statement)
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::Hf,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ => {
+            if let Some(reg) = upper.strip_prefix('V') {
+                if reg.len() == 1 {
+                    if let Ok(x) = u8::from_str_radix(reg, 16) {
+                        return Ok(Operand::V(x));
+                    }
+                }
+            }
+            if let Some(addr) = upper.strip_prefix("LONG ") {
+                // Keep the original case, for labels.
+                let addr = &text[text.len() - addr.len()..];
+                return Ok(Operand::Long(parse_value(addr.trim())?));
+            }
+            Operand::Value(parse_value(text)?)
+        }
+    };
+    Ok(operand)
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if is_identifier(text) {
+        return Ok(Value::Label(text.to_string()));
+    }
+
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let lower = digits.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        lower.parse()
+    };
+
+    let n = parsed.map_err(|_| format!("invalid operand {text:?}"))?;
+    Ok(Value::Number(if negative { -n } else { n }))
+}
+
+fn check_range(n: i64, min: i64, max: i64, what: &str) -> Result<i64, String> {
+    if n < min || n > max {
+        return Err(format!("{what} out of range: {n}"));
+    }
+    Ok(n)
+}
+
+const MNEMONICS: [&str; 30] = [
+    "CLS", "RET", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE", "SAVE",
+    "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP",
+    "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+fn encode_instruction(
+    mnemonic: &str,
+    operands: &[Operand],
+    labels: &HashMap<String, usize>,
+) -> Result<Instruction, String> {
+    use Instruction::*;
+    use Operand as O;
+
+    let value = |v: &Value, max: i64, what: &str| check_range(v.resolve(labels)?, 0, max, what);
+    let addr = |v: &Value| value(v, 0xfff, "address").map(|n| n as u16);
+    let byte = |v: &Value| {
+        // Allow negative numbers, as two's complement.
+        check_range(v.resolve(labels)?, -0x80, 0xff, "byte").map(|n| n as u8)
+    };
+    let nibble = |v: &Value| value(v, 0xf, "nibble").map(|n| n as u8);
+
+    let instr = match (mnemonic, operands) {
+        ("CLS", []) => Cls,
+        ("RET", []) => Ret,
+        ("SCD", [O::Value(n)]) => ScrollDown(nibble(n)?),
+        ("SCR", []) => ScrollRight,
+        ("SCL", []) => ScrollLeft,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => Lores,
+        ("HIGH", []) => Hires,
+        ("JP", [O::Value(a)]) => Jump(addr(a)?),
+        ("JP", [O::V(0), O::Value(a)]) => JumpOffset(addr(a)?),
+        ("CALL", [O::Value(a)]) => Call(addr(a)?),
+        ("SE", [O::V(x), O::Value(k)]) => SkipEqImm(*x, byte(k)?),
+        ("SE", [O::V(x), O::V(y)]) => SkipEqReg(*x, *y),
+        ("SNE", [O::V(x), O::Value(k)]) => SkipNeImm(*x, byte(k)?),
+        ("SNE", [O::V(x), O::V(y)]) => SkipNeReg(*x, *y),
+        ("SAVE", [O::V(x), O::V(y)]) => SaveRange(*x, *y),
+        ("LOAD", [O::V(x), O::V(y)]) => LoadRange(*x, *y),
+        ("LD", [O::V(x), O::Value(k)]) => LoadImm(*x, byte(k)?),
+        ("LD", [O::V(x), O::V(y)]) => Move(*x, *y),
+        ("LD", [O::I, O::Value(a)]) => LoadI(addr(a)?),
+        ("LD", [O::I, O::Long(a)]) => LoadILong(value(a, 0xffff, "address")? as u16),
+        ("LD", [O::V(x), O::Dt]) => GetDelay(*x),
+        ("LD", [O::V(x), O::K]) => WaitKey(*x),
+        ("LD", [O::Dt, O::V(x)]) => SetDelay(*x),
+        ("LD", [O::St, O::V(x)]) => SetSound(*x),
+        ("LD", [O::F, O::V(x)]) => Font(*x),
+        ("LD", [O::Hf, O::V(x)]) => BigFont(*x),
+        ("LD", [O::B, O::V(x)]) => Bcd(*x),
+        ("LD", [O::IndirectI, O::V(x)]) => Store(*x),
+        ("LD", [O::V(x), O::IndirectI]) => Load(*x),
+        ("LD", [O::R, O::V(x)]) => SaveFlags(*x),
+        ("LD", [O::V(x), O::R]) => LoadFlags(*x),
+        ("ADD", [O::V(x), O::Value(k)]) => AddImm(*x, byte(k)?),
+        ("ADD", [O::V(x), O::V(y)]) => Add(*x, *y),
+        ("ADD", [O::I, O::V(x)]) => AddI(*x),
+        ("OR", [O::V(x), O::V(y)]) => Or(*x, *y),
+        ("AND", [O::V(x), O::V(y)]) => And(*x, *y),
+        ("XOR", [O::V(x), O::V(y)]) => Xor(*x, *y),
+        ("SUB", [O::V(x), O::V(y)]) => Sub(*x, *y),
+        ("SUBN", [O::V(x), O::V(y)]) => SubN(*x, *y),
+        ("SHR", [O::V(x)]) => Shr(*x, *x),
+        ("SHR", [O::V(x), O::V(y)]) => Shr(*x, *y),
+        ("SHL", [O::V(x)]) => Shl(*x, *x),
+        ("SHL", [O::V(x), O::V(y)]) => Shl(*x, *y),
+        ("RND", [O::V(x), O::Value(k)]) => Rand(*x, byte(k)?),
+        ("DRW", [O::V(x), O::V(y), O::Value(n)]) => Draw(*x, *y, nibble(n)?),
+        ("SKP", [O::V(x)]) => SkipKey(*x),
+        ("SKNP", [O::V(x)]) => SkipNotKey(*x),
+        ("PLANE", [O::Value(n)]) => Plane(value(n, 0b11, "plane mask")? as u8),
+        ("AUDIO", []) => Audio,
+        ("PITCH", [O::V(x)]) => Pitch(*x),
+        _ if MNEMONICS.contains(&mnemonic) => {
+            return Err(format!("{mnemonic}: invalid operands"));
+        }
+        _ => return Err(format!("unknown instruction {mnemonic:?}")),
+    };
+    Ok(instr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for opcode in 0..=u16::MAX {
+            let Some(instr) = Instruction::decode(opcode) else {
+                continue;
+            };
+            let source = instr.to_string();
+            let rom = assemble(&source).unwrap_or_else(|e| panic!("{source:?}: {e}"));
+            assert_eq!(Some(rom), instr.to_bytes(), "{source:?}");
+        }
+
+        let instr = Instruction::LoadILong(0xbeef);
+        assert_eq!(assemble(&instr.to_string()).ok(), instr.to_bytes());
+    }
+
+    #[test]
+    fn labels() {
+        let rom = assemble("start: JP end\nend: JP start").unwrap();
+        assert_eq!(rom, [0x12, 0x02, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn reserved_labels() {
+        for name in ["b", "F", "k", "r", "i", "dt", "ST", "hf", "v0", "VF"] {
+            let err = assemble(&format!("JP {name}\n{name}: CLS")).unwrap_err();
+            assert_eq!(err.line, 2, "{name}");
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Finding the bytes of memory that hold things like lives or the level,
+//! and freezing them.
+//!
+//! A `Search` starts with every address as a candidate, and each
+//! `Search::narrow` keeps the ones whose value changed, stayed the same, or
+//! equals some number since the last time. Once it's down to a few, a
+//! `Cheat` can hold one of them at a value.
+//!
+//! A `CheatList` is saved as text, one cheat per line: the address and
+//! value in hex, and an optional note.
+//!
+//! ```text
+//! # lives
+//! 2f0 09
+//! 2f4 03 level
+//! ```
+
+use crate::ParseError;
+use std::fmt::{self, Display};
+
+/// How `Search::narrow` filters the candidates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Changed,
+    Unchanged,
+    Equals(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Search {
+    /// Memory as of the last `narrow`.
+    snapshot: Vec<u8>,
+    /// Addresses that passed every filter so far, in order.
+    candidates: Vec<u16>,
+}
+
+impl Search {
+    /// Start with every address in `mem`.
+    pub fn new(mem: &[u8]) -> Self {
+        Self {
+            snapshot: mem.to_vec(),
+            candidates: (0..mem.len()).map(|addr| addr as u16).collect(),
+        }
+    }
+
+    /// Compare `mem` against the last snapshot, and keep the candidates that
+    /// match.
+    ///
+    /// Returns false, and changes nothing, if `mem` isn't the same size as
+    /// the memory the search started with.
+    pub fn narrow(&mut self, mem: &[u8], filter: Filter) -> bool {
+        if mem.len() != self.snapshot.len() {
+            return false;
+        }
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&addr| {
+            let old = snapshot[addr as usize];
+            let new = mem[addr as usize];
+            match filter {
+                Filter::Changed => new != old,
+                Filter::Unchanged => new == old,
+                Filter::Equals(n) => new == n,
+            }
+        });
+        self.snapshot.copy_from_slice(mem);
+        true
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+/// Hold `addr` at `value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub addr: u16,
+    pub value: u8,
+    /// What it's for, e.g. "lives". May be empty.
+    pub note: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the text format. Blank lines and lines starting with `#` are
+    /// skipped. Fields can be separated by any amount of whitespace, and
+    /// the note is the rest of the line, with single spaces between words.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut list = Self::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: &str| ParseError {
+                line: n + 1,
+                message: message.to_string(),
+            };
+            let mut fields = line.split_whitespace();
+            let addr = fields
+                .next()
+                .and_then(|s| u16::from_str_radix(s, 16).ok())
+                .ok_or_else(|| error("bad address"))?;
+            let value = fields
+                .next()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(|| error("bad value"))?;
+            let note = fields.collect::<Vec<_>>().join(" ");
+
+            list.freeze(Cheat { addr, value, note });
+        }
+        Ok(list)
+    }
+
+    /// Add a cheat, replacing any other one for the same address. If it
+    /// doesn't have a note, it keeps the old one's.
+    pub fn freeze(&mut self, mut cheat: Cheat) {
+        match self.cheats.iter_mut().find(|c| c.addr == cheat.addr) {
+            Some(old) => {
+                if cheat.note.is_empty() {
+                    cheat.note = std::mem::take(&mut old.note);
+                }
+                *old = cheat;
+            }
+            None => self.cheats.push(cheat),
+        }
+    }
+
+    /// Returns false if there wasn't a cheat for `addr`.
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        let len = self.cheats.len();
+        self.cheats.retain(|c| c.addr != addr);
+        self.cheats.len() != len
+    }
+
+    /// Write every cheat's value into `mem`. Call this once per frame.
+    pub fn apply(&self, mem: &mut [u8]) {
+        for cheat in &self.cheats {
+            if let Some(byte) = mem.get_mut(cheat.addr as usize) {
+                *byte = cheat.value;
+            }
+        }
+    }
+
+    pub fn as_slice(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+}
+
+/// The text format that `CheatList::parse` reads.
+impl Display for CheatList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for cheat in &self.cheats {
+            write!(f, "{:03x} {:02x}", cheat.addr, cheat.value)?;
+            if !cheat.note.is_empty() {
+                write!(f, " {}", cheat.note)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A hash of the ROM (64-bit FNV-1a), to keep each ROM's cheats separate.
+/// Unlike `std`'s hashers, it's guaranteed not to change between versions.
+pub fn rom_id(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cheat(addr: u16, value: u8, note: &str) -> Cheat {
+        Cheat {
+            addr,
+            value,
+            note: note.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut list = CheatList::new();
+        list.freeze(cheat(0x2f0, 0x09, "lives"));
+        list.freeze(cheat(0x2f4, 0x03, ""));
+        list.freeze(cheat(0xfff0, 0xff, "extra lives"));
+
+        let text = list.to_string();
+        assert_eq!(text, "2f0 09 lives\n2f4 03\nfff0 ff extra lives\n");
+        assert_eq!(CheatList::parse(&text), Ok(list));
+    }
+
+    #[test]
+    fn parse() {
+        let text = "
+            # lives
+            2f0\t9   extra   lives
+
+            # comment 2f4 03
+              2f4  03
+        ";
+        let list = CheatList::parse(text).unwrap();
+        assert_eq!(
+            list.as_slice(),
+            [cheat(0x2f0, 0x09, "extra lives"), cheat(0x2f4, 0x03, "")]
+        );
+        assert_eq!(CheatList::parse(""), Ok(CheatList::new()));
+
+        for (text, line, message) in [
+            ("xyz 09", 1, "bad address"),
+            ("2f0 09\n\n10000 09", 3, "bad address"),
+            ("# 2f0\n2f0", 2, "bad value"),
+            ("2f0 100", 1, "bad value"),
+            ("2f0 -1", 1, "bad value"),
+        ] {
+            let expected = ParseError {
+                line,
+                message: message.to_string(),
+            };
+            assert_eq!(CheatList::parse(text), Err(expected), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn freeze_and_unfreeze() {
+        let mut list = CheatList::new();
+        list.freeze(cheat(0x2f0, 0x09, "lives"));
+        list.freeze(cheat(0x2f4, 0x03, "level"));
+
+        // Replaces the value, but keeps the note unless there's a new one.
+        list.freeze(cheat(0x2f0, 0x05, ""));
+        assert_eq!(list.as_slice()[0], cheat(0x2f0, 0x05, "lives"));
+        list.freeze(cheat(0x2f0, 0x05, "hearts"));
+        assert_eq!(list.as_slice()[0], cheat(0x2f0, 0x05, "hearts"));
+        assert_eq!(list.as_slice().len(), 2);
+
+        let mut mem = [0; 0x300];
+        list.apply(&mut mem);
+        assert_eq!((mem[0x2f0], mem[0x2f4]), (0x05, 0x03));
+
+        assert!(list.unfreeze(0x2f0));
+        assert!(!list.unfreeze(0x2f0));
+        assert!(!list.unfreeze(0x123));
+        assert_eq!(list.as_slice(), [cheat(0x2f4, 0x03, "level")]);
+        assert!(list.unfreeze(0x2f4));
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn narrow() {
+        let mut mem = [0, 1, 2, 3, 4, 5];
+        let mut search = Search::new(&mem);
+        assert_eq!(search.candidates(), [0, 1, 2, 3, 4, 5]);
+
+        mem[1] = 9;
+        mem[2] = 9;
+        mem[3] = 9;
+        assert!(search.narrow(&mem, Filter::Changed));
+        assert_eq!(search.candidates(), [1, 2, 3]);
+
+        // Compared against the last narrow, not the start.
+        mem[2] = 7;
+        assert!(search.narrow(&mem, Filter::Unchanged));
+        assert_eq!(search.candidates(), [1, 3]);
+
+        mem[3] = 7;
+        assert!(search.narrow(&mem, Filter::Equals(7)));
+        assert_eq!(search.candidates(), [3]);
+
+        assert!(!search.narrow(&mem[..4], Filter::Changed));
+        assert_eq!(search.candidates(), [3]);
+    }
+
+    #[test]
+    fn rom_id() {
+        assert_eq!(super::rom_id(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(super::rom_id(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(super::rom_id(&[0x12, 0x00]), super::rom_id(&[0x00, 0x12]));
+    }
+}
@@ -0,0 +1,221 @@
+//! Which bytes of memory a run executed as instructions, read as data, or
+//! wrote, for telling code from data in a ROM.
+//!
+//! `Coverage::listing` uses that instead of guessing like
+//! `disasm::disassemble`: only bytes that were executed are decoded as
+//! instructions. Each line is marked with `X` if it was executed, `R` if it
+//! was read as data (by `Dxyn`, `Fx65`, and so on), and `W` if it was
+//! written, with `.` for each that didn't happen.
+
+use crate::cpu::{io::Chip8Io, mem::Mem, Chip8};
+use crate::disasm::{self, Line};
+use std::fmt::{self, Display};
+
+const EXECUTED: u8 = 1 << 0;
+const READ: u8 = 1 << 1;
+const WRITTEN: u8 = 1 << 2;
+
+/// How many untouched or data bytes go on one line of the listing.
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// `EXECUTED`, `READ`, and `WRITTEN` bits for each address.
+    marks: Vec<u8>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self {
+            marks: vec![0; Mem::XO_CHIP_LEN],
+        }
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call before each instruction, e.g. from the `stop` callback of
+    /// `Chip8::run_frame_until`. This also picks up the data the previous
+    /// instruction read and wrote.
+    pub fn record<IO: Chip8Io>(&mut self, chip8: &Chip8<IO>) {
+        self.finish(chip8);
+
+        let pc = chip8.pc() as usize;
+        let len = chip8.instruction_at(chip8.pc()).map_or(2, |i| i.size()) as usize;
+        self.mark(pc, len, EXECUTED);
+    }
+
+    /// Pick up the data the last instruction read and wrote. Call this when
+    /// the run is over, since `record` only sees it before the next one.
+    pub fn finish<IO: Chip8Io>(&mut self, chip8: &Chip8<IO>) {
+        for access in chip8.last_accesses() {
+            let mark = if access.write { WRITTEN } else { READ };
+            self.mark(access.start, access.len, mark);
+        }
+    }
+
+    fn mark(&mut self, start: usize, len: usize, mark: u8) {
+        let end = (start + len).min(self.marks.len());
+        for m in &mut self.marks[start.min(end)..end] {
+            *m |= mark;
+        }
+    }
+
+    /// `(executed, read or written, untouched)` byte counts, within the ROM
+    /// starting at `Mem::ROM_START`.
+    pub fn summary(&self, rom_len: usize) -> (usize, usize, usize) {
+        let start = Mem::ROM_START as usize;
+        let marks = &self.marks[start..start + rom_len];
+        let executed = marks.iter().filter(|&&m| m & EXECUTED != 0).count();
+        let data = marks
+            .iter()
+            .filter(|&&m| m != 0 && m & EXECUTED == 0)
+            .count();
+        let untouched = marks.iter().filter(|&&m| m == 0).count();
+        (executed, data, untouched)
+    }
+
+    /// Disassemble `rom`, decoding only the bytes that were executed.
+    pub fn listing(&self, rom: &[u8]) -> Vec<ListingLine> {
+        let mut lines = vec![];
+
+        let mut offset = 0;
+        while offset < rom.len() {
+            let addr = Mem::ROM_START as usize + offset;
+            let first = self.marks[addr];
+
+            let instr = match first & EXECUTED {
+                0 => None,
+                _ => disasm::decode_prefix(&rom[offset..]),
+            };
+            let len = match instr {
+                Some(instr) => instr.size() as usize,
+                // Group data bytes with the same marks.
+                None => rom[offset..]
+                    .iter()
+                    .zip(&self.marks[addr..])
+                    .take(DATA_PER_LINE)
+                    .take_while(|&(_, &m)| m == first)
+                    .count(),
+            };
+            let len = len.min(rom.len() - offset);
+            let marks = self.marks[addr..][..len].iter().fold(0, |all, &m| all | m);
+
+            lines.push(ListingLine {
+                marks,
+                line: Line {
+                    addr: addr as u16,
+                    bytes: rom[offset..][..len].to_vec(),
+                    instr,
+                },
+            });
+            offset += len;
+        }
+
+        lines
+    }
+}
+
+/// A line of `Coverage::listing`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingLine {
+    /// For every byte of the line together, so an instruction counts as
+    /// read if any part of it was.
+    marks: u8,
+    pub line: Line,
+}
+
+impl ListingLine {
+    pub fn executed(&self) -> bool {
+        self.marks & EXECUTED != 0
+    }
+
+    pub fn read(&self) -> bool {
+        self.marks & READ != 0
+    }
+
+    pub fn written(&self) -> bool {
+        self.marks & WRITTEN != 0
+    }
+}
+
+impl Display for ListingLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '.' };
+        write!(
+            f,
+            "{}{}{}  {}",
+            flag(self.executed(), 'X'),
+            flag(self.read(), 'R'),
+            flag(self.written(), 'W'),
+            self.line
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, HeadlessIo, Quirks};
+
+    fn listing(source: &str) -> Vec<String> {
+        let rom = asm::assemble(source).unwrap();
+        let mut chip8 =
+            Chip8::new(&rom, Quirks::preset("xochip").unwrap(), HeadlessIo::new(0)).unwrap();
+        let mut coverage = Coverage::new();
+        chip8
+            .run_frame_until(|chip8| {
+                coverage.record(chip8);
+                false
+            })
+            .unwrap();
+        coverage.finish(&chip8);
+        coverage
+            .listing(&rom)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn marks() {
+        // XO-CHIP moves `i` past what `Fx65` read, so `Fx55` writes after it.
+        let lines = listing(
+            "
+                    LD I, data
+                    LD V1, [I]
+                    LD [I], V0
+            end:    JP end
+            data:   :byte 1, 2, 3
+            ",
+        );
+        assert_eq!(
+            lines,
+            [
+                "X..  0x200  a2 08        LD I, 0x208",
+                "X..  0x202  f1 65        LD V1, [I]",
+                "X..  0x204  f0 55        LD [I], V0",
+                "X..  0x206  12 06        JP 0x206",
+                ".R.  0x208  01 02        :byte 0x01, 0x02",
+                "..W  0x20a  03           :byte 0x03",
+            ]
+        );
+    }
+
+    #[test]
+    fn long_instruction() {
+        // Reads the second word of the `F000 NNNN` that set `i`.
+        let lines = listing(
+            "
+                    LD I, long 0x202
+                    LD V1, [I]
+            end:    JP end
+            ",
+        );
+        assert_eq!(lines[0], "XR.  0x200  f0 00 02 02  LD I, long 0x0202");
+        assert_eq!(lines[1], "X..  0x204  f1 65        LD V1, [I]");
+    }
+}
@@ -1,224 +1,758 @@
 mod debug;
-mod mem;
-mod regs;
 mod stack;
 
+pub mod error;
+pub mod instruction;
 pub mod io;
+pub(crate) mod mem;
+pub mod quirks;
+pub(crate) mod regs;
+pub mod rewind;
+pub mod rng;
 pub mod screen;
+pub mod state;
 
 use self::io::Chip8Io;
-use mem::Mem;
+use crate::cheat;
+use error::{Chip8Error, Fault};
+use instruction::{Instruction, LONG_PREFIX};
+use mem::{Mem, MemAccess};
+use quirks::{LoadStoreI, Quirks};
 use regs::Regs;
-use screen::Point;
+use rng::VipRng;
+use screen::{Point, Screen, Sprite};
 use stack::Stack;
+use state::{StateError, StateReader, StateWriter};
 use std::fmt::Debug;
 
-#[derive(Debug)]
-pub struct Chip8<'a> {
+/// The default number of instructions `Chip8::run_frame` executes per 60 Hz
+/// frame. This is about the speed of the COSMAC VIP; SUPER-CHIP programs
+/// usually want 30 or more.
+pub const INSTRUCTIONS_PER_FRAME: usize = 10;
+
+/// A CHIP-8 interpreter, which owns its IO backend.
+///
+/// It's `Clone` whenever the backend is.
+#[derive(Debug, Clone)]
+pub struct Chip8<IO: Chip8Io> {
     pc: u16,
     i: u16,
     stack: Stack,
     v: Regs,
     mem: Mem,
-    io: &'a mut dyn Chip8Io,
+    /// SUPER-CHIP "RPL user flags", saved and restored by `Fx75`/`Fx85`.
+    flags: Regs,
+    screen: Screen,
+    dt: u8,
+    st: u8,
+    /// The virtual clock: how many instructions have run, counting each
+    /// check of `Fx0A` while it waits for a key, and skipping ahead to the
+    /// next frame for the `display_wait` quirk. The timers tick each time it
+    /// reaches a multiple of `instructions_per_frame`.
+    cycles: u64,
+    instructions_per_frame: u64,
+    /// Has the screen changed since it was last presented?
+    screen_dirty: bool,
+    /// Set by a `Dxyn` that should wait for the next frame.
+    vblank_pending: bool,
+    /// Set by the SUPER-CHIP `00FD` instruction.
+    exited: bool,
+    quirks: Quirks,
+    /// `cheat::rom_id` of the ROM we started with. Save states record it, so
+    /// that one ROM's states can't be loaded into another.
+    rom_id: u64,
+    /// If set, `Cxkk` uses this instead of `Chip8Io::get_random_byte`.
+    vip_rng: Option<VipRng>,
+    /// Memory read or written as data by the last step, for watchpoints.
+    accesses: Vec<MemAccess>,
+    io: IO,
 }
 
-impl<'a> Chip8<'a> {
-    pub fn new(rom: &[u8], io: &'a mut dyn Chip8Io) -> Self {
-        Self {
+impl<IO: Chip8Io> Chip8<IO> {
+    pub fn new(rom: &[u8], quirks: Quirks, io: IO) -> Result<Self, Chip8Error> {
+        Ok(Self {
             pc: Mem::ROM_START,
             i: 0,
             stack: Stack::new(),
             v: Regs::new(),
-            mem: Mem::new(rom),
+            mem: Mem::new(rom, quirks.mem_len)?,
+            flags: Regs::new(),
+            screen: Screen::new(),
+            dt: 0,
+            st: 0,
+            cycles: 0,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME as u64,
+            screen_dirty: false,
+            vblank_pending: false,
+            exited: false,
+            quirks,
+            rom_id: cheat::rom_id(rom),
+            vip_rng: None,
+            accesses: vec![],
             io,
+        })
+    }
+
+    pub fn io(&self) -> &IO {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut IO {
+        &mut self.io
+    }
+
+    /// Consume the interpreter, and give back the IO backend.
+    pub fn into_io(self) -> IO {
+        self.io
+    }
+
+    /// Use the COSMAC VIP's random number routine for `Cxkk`, or `None` to go
+    /// back to `Chip8Io::get_random_byte`.
+    pub fn set_vip_rng(&mut self, rng: Option<VipRng>) {
+        self.vip_rng = rng;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn v(&self) -> &Regs {
+        &self.v
+    }
+
+    pub fn v_mut(&mut self) -> &mut Regs {
+        &mut self.v
+    }
+
+    /// The call stack's return addresses, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        self.stack.as_slice()
+    }
+
+    /// A few rows of memory as a hexdump, starting with the row containing
+    /// `addr`.
+    pub fn hexdump(&self, addr: u16, rows: usize) -> impl Debug + '_ {
+        self.mem.hexdump(addr as usize, rows)
+    }
+
+    /// Decode the instruction at `addr` without running it. `None` if it's
+    /// out of bounds or not a valid instruction.
+    pub fn instruction_at(&self, addr: u16) -> Option<Instruction> {
+        let opcode = self.read_word(addr)?;
+        if opcode == LONG_PREFIX {
+            Some(Instruction::LoadILong(
+                self.read_word(addr.wrapping_add(2))?,
+            ))
+        } else {
+            Instruction::decode(opcode)
         }
     }
 
-    pub fn run(mut self) {
-        loop {
-            // Detect "halt" instruction.
-            // This is a hack to make testing easier.
-            if self.would_halt() {
-                break;
+    /// All of memory, including the interpreter area below `0x200`.
+    pub fn memory(&self) -> &[u8] {
+        self.mem.as_slice()
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.mem.as_mut_slice()
+    }
+
+    /// The memory that the last instruction read or wrote, not counting
+    /// fetching the instruction itself.
+    pub fn last_accesses(&self) -> &[MemAccess] {
+        &self.accesses
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    /// The virtual clock, in instructions.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame as usize
+    }
+
+    /// Set the emulation speed. The default is `INSTRUCTIONS_PER_FRAME`.
+    pub fn set_instructions_per_frame(&mut self, n: usize) {
+        assert!(n > 0, "instructions per frame must be positive");
+        self.instructions_per_frame = n as u64;
+    }
+
+    /// Snapshot the whole machine, including the state of the `Chip8Io`
+    /// backend. See `state` for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::default();
+        w.bytes(state::MAGIC);
+        w.u8(state::VERSION);
+        w.u64(self.rom_id);
+
+        w.u16(self.pc);
+        w.u16(self.i);
+        self.stack.save(&mut w);
+        self.v.save(&mut w);
+        self.mem.save(&mut w);
+        self.flags.save(&mut w);
+        self.screen.save(&mut w);
+        w.u8(self.dt);
+        w.u8(self.st);
+        w.u64(self.cycles);
+        w.bool(self.exited);
+        w.bool(self.vip_rng.is_some());
+        if let Some(rng) = &self.vip_rng {
+            rng.save(&mut w);
+        }
+
+        self.io.save_state(&mut w);
+        w.finish()
+    }
+
+    /// Restore a snapshot taken by `save_state`.
+    ///
+    /// The quirks aren't part of the snapshot, and stay as they are.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(bytes);
+        if r.bytes(state::MAGIC.len()) != Ok(state::MAGIC) {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u8()?;
+        if version != state::VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if r.u64()? != self.rom_id {
+            return Err(StateError::WrongRom);
+        }
+
+        let pc = r.u16()?;
+        let i = r.u16()?;
+        let stack = Stack::load(&mut r)?;
+        let v = Regs::load(&mut r)?;
+        let mem = Mem::load(&mut r, self.mem.len())?;
+        let flags = Regs::load(&mut r)?;
+        let screen = Screen::load(&mut r)?;
+        let dt = r.u8()?;
+        let st = r.u8()?;
+        let cycles = r.u64()?;
+        let exited = r.bool()?;
+        let vip_rng = if r.bool()? {
+            // Keep whichever code page we were given.
+            let rng = self.vip_rng.clone().unwrap_or_else(|| VipRng::new(0));
+            Some(rng.load(&mut r)?)
+        } else {
+            None
+        };
+
+        // This checks that there's nothing left over, so it goes last.
+        self.io.load_state(&mut r)?;
+
+        self.pc = pc;
+        self.i = i;
+        self.stack = stack;
+        self.v = v;
+        self.mem = mem;
+        self.flags = flags;
+        self.screen = screen;
+        self.dt = dt;
+        self.st = st;
+        self.cycles = cycles;
+        self.exited = exited;
+        self.vip_rng = vip_rng;
+
+        self.accesses.clear();
+        self.screen_dirty = false;
+        self.io.present(&self.screen);
+        Ok(())
+    }
+
+    /// Run until the program halts, calling `Chip8Io::wait_for_frame`
+    /// between frames.
+    pub fn run(&mut self) -> Result<(), Chip8Error> {
+        while self.run_frame()? != Step::Halted {
+            self.io.wait_for_frame();
+        }
+        Ok(())
+    }
+
+    /// Run one 60 Hz frame: execute instructions until the virtual clock
+    /// reaches the start of the next frame (which ticks the timers), then
+    /// present the screen if it changed, and poll for input once with
+    /// `Chip8Io::update`.
+    ///
+    /// The frame ends early if the program waits for a key or for vblank,
+    /// and stops if the program halts. Returns the last `Step`.
+    pub fn run_frame(&mut self) -> Result<Step, Chip8Error> {
+        self.run_frame_until(|_| false)
+    }
+
+    /// Like `run_frame`, but check `stop` before each instruction, e.g. for
+    /// breakpoints. If it returns true, present the screen and return
+    /// `Step::Stopped` right away. The next call picks up the rest of the
+    /// frame.
+    ///
+    /// `stop` is called once more after the program halts, so watchpoints
+    /// can see what the last instruction did. Use `halted` to tell.
+    pub fn run_frame_until(
+        &mut self,
+        mut stop: impl FnMut(&Self) -> bool,
+    ) -> Result<Step, Chip8Error> {
+        let end = (self.cycles / self.instructions_per_frame + 1) * self.instructions_per_frame;
+
+        let mut step = Step::Executed;
+        while self.cycles < end {
+            if stop(self) {
+                self.present();
+                return Ok(Step::Stopped);
+            }
+
+            step = self.step()?;
+            match step {
+                Step::Executed | Step::Stopped => (),
+                // There's no new input until the next frame.
+                Step::WaitingForKey => self.wait_for_vblank(),
+                Step::Halted => break,
             }
+        }
+
+        self.present();
+        self.io.update();
+
+        Ok(step)
+    }
 
-            self.step();
-            //eprintln!("{:#04x?}", self);
+    /// Present the screen, if it changed since the last time. `run_frame`
+    /// does this at the end of each frame, but a debugger stepping through
+    /// instructions might want to see it sooner.
+    pub fn present(&mut self) {
+        if self.screen_dirty {
+            self.screen_dirty = false;
+            self.io.present(&self.screen);
+        }
+    }
+
+    /// Has the program exited, or is it stuck jumping to itself?
+    pub fn halted(&self) -> bool {
+        self.exited || self.read_word(self.pc) == Some(0x1000 | self.pc)
+    }
 
+    /// Execute a single instruction for a debugger. If it's waiting for a
+    /// key, poll for input with `Chip8Io::update`, so that the next step can
+    /// see it.
+    pub fn single_step(&mut self) -> Result<Step, Chip8Error> {
+        let step = self.step()?;
+        if step == Step::WaitingForKey {
             self.io.update();
         }
+        Ok(step)
     }
 
-    fn would_halt(&self) -> bool {
-        let j = self.mem[self.pc];
-        let k = self.mem[self.pc + 1];
-        let instr = u16::from_be_bytes([j, k]);
-        instr == 0x1000 | self.pc
+    /// Big endian. `None` if out of bounds.
+    fn read_word(&self, addr: u16) -> Option<u16> {
+        let j = self.mem.get(addr as usize)?;
+        let k = self.mem.get(addr as usize + 1)?;
+        Some(u16::from_be_bytes([j, k]))
     }
 
-    fn step(&mut self) {
-        debug_assert!(self.pc < Mem::LEN);
+    /// Execute a single instruction.
+    pub fn step(&mut self) -> Result<Step, Chip8Error> {
+        // Detect "halt" instruction.
+        // This is a hack to make testing easier.
+        if self.halted() {
+            return Ok(Step::Halted);
+        }
 
-        let j = self.mem[self.pc];
-        let k = self.mem[self.pc + 1];
-        let instr = u16::from_be_bytes([j, k]);
-        let old_pc = self.pc;
-        let err = || panic!("unimplemented: 0x{instr:04x} (pc=0x{old_pc:04x})");
-        self.pc += 2;
+        self.accesses.clear();
+        let pc = self.pc;
+        let opcode = self.read_word(pc).ok_or(Chip8Error::PcOutOfBounds { pc })?;
 
-        let [op, x, y, n] = nibbles_from_u16(instr);
-        let addr = instr & 0x0fff;
+        let step = self
+            .fetch(opcode)
+            .and_then(|instr| self.execute(instr))
+            .map_err(|fault| fault.at(pc, opcode))?;
 
-        match op {
-            0x0 => match instr {
-                0x00e0 => self.io.clear_screen(),
-                0x00ee => self.pc = self.stack.pop(),
-                _ => err(),
-            },
-            0x1 => self.pc = addr,
-            0x2 => {
-                self.stack.push(self.pc);
+        self.tick();
+        if self.vblank_pending {
+            self.vblank_pending = false;
+            self.wait_for_vblank();
+        }
+
+        Ok(step)
+    }
+
+    /// Advance the virtual clock by one instruction.
+    fn tick(&mut self) {
+        self.cycles += 1;
+        if self.cycles.is_multiple_of(self.instructions_per_frame) {
+            self.tick_timers();
+        }
+    }
+
+    /// Skip the virtual clock ahead to the start of the next frame, unless
+    /// it's already there.
+    fn wait_for_vblank(&mut self) {
+        let into_frame = self.cycles % self.instructions_per_frame;
+        if into_frame != 0 {
+            self.cycles += self.instructions_per_frame - into_frame;
+            self.tick_timers();
+        }
+    }
+
+    fn tick_timers(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+    }
+
+    /// Decode the instruction at `self.pc`, and advance `self.pc` past it.
+    fn fetch(&mut self, opcode: u16) -> Result<Instruction, Fault> {
+        let instr = if opcode == LONG_PREFIX {
+            // Not `load`, since this isn't a data access.
+            let addr = self.pc.wrapping_add(2);
+            let operand = self
+                .read_word(addr)
+                .ok_or(Fault::MemOutOfBounds(addr as usize))?;
+            Instruction::LoadILong(operand)
+        } else {
+            Instruction::decode(opcode).ok_or(Fault::UnknownOpcode)?
+        };
+
+        self.pc = self.pc.wrapping_add(instr.size());
+        Ok(instr)
+    }
+
+    /// Execute one instruction. `self.pc` should already point past it.
+    fn execute(&mut self, instr: Instruction) -> Result<Step, Fault> {
+        use Instruction::*;
+
+        match instr {
+            ScrollDown(n) => {
+                self.screen.scroll_down(n);
+                self.screen_dirty = true;
+            }
+            Cls => {
+                self.screen.clear();
+                self.screen_dirty = true;
+            }
+            Ret => self.pc = self.stack.pop()?,
+            ScrollRight => {
+                self.screen.scroll_right();
+                self.screen_dirty = true;
+            }
+            ScrollLeft => {
+                self.screen.scroll_left();
+                self.screen_dirty = true;
+            }
+            Exit => self.exited = true,
+            Lores => self.set_hires(false),
+            Hires => self.set_hires(true),
+            Jump(addr) => self.pc = addr,
+            Call(addr) => {
+                self.stack.push(self.pc)?;
                 self.pc = addr;
             }
-            0x3 => {
+            SkipEqImm(x, k) => {
                 if self.v[x] == k {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
-            0x4 => {
+            SkipNeImm(x, k) => {
                 if self.v[x] != k {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
-            0x5 => {
-                assert_eq!(n, 0);
+            SkipEqReg(x, y) => {
                 if self.v[x] == self.v[y] {
-                    self.pc += 2;
+                    self.skip();
+                }
+            }
+            SaveRange(x, y) => {
+                // Write the registers vx through vy (in either order) to memory.
+                for (offset, reg) in reg_range(x, y).enumerate() {
+                    self.store(self.i as usize + offset, self.v[reg])?;
                 }
             }
-            0x6 => self.v[x] = k,
-            0x7 => self.v[x] = self.v[x].wrapping_add(k),
-            0x8 => match n {
-                0x0 => self.v[x] = self.v[y],
-                0x1 => {
-                    self.v[x] |= self.v[y];
+            LoadRange(x, y) => {
+                // Read memory into the registers vx through vy.
+                for (offset, reg) in reg_range(x, y).enumerate() {
+                    self.v[reg] = self.load(self.i as usize + offset)?;
+                }
+            }
+            LoadImm(x, k) => self.v[x] = k,
+            AddImm(x, k) => self.v[x] = self.v[x].wrapping_add(k),
+            Move(x, y) => self.v[x] = self.v[y],
+            Or(x, y) => {
+                self.v[x] |= self.v[y];
+                if self.quirks.logic_resets_vf {
                     self.v[0xf] = 0;
                 }
-                0x2 => {
-                    self.v[x] &= self.v[y];
+            }
+            And(x, y) => {
+                self.v[x] &= self.v[y];
+                if self.quirks.logic_resets_vf {
                     self.v[0xf] = 0;
                 }
-                0x3 => {
-                    self.v[x] ^= self.v[y];
+            }
+            Xor(x, y) => {
+                self.v[x] ^= self.v[y];
+                if self.quirks.logic_resets_vf {
                     self.v[0xf] = 0;
                 }
-                0x4 => {
-                    let (sum, carry) = self.v[x].overflowing_add(self.v[y]);
-                    self.v[x] = sum;
-                    self.v[0xf] = carry as u8;
-                }
-                0x5 => {
-                    let (diff, borrow) = self.v[x].overflowing_sub(self.v[y]);
-                    self.v[x] = diff;
-                    self.v[0xf] = !borrow as u8;
-                }
-                0x6 => {
-                    let shift = self.v[y] >> 1;
-                    let carry = self.v[y] % 2;
-                    self.v[x] = shift;
-                    self.v[0xf] = carry;
-                }
-                0x7 => {
-                    // y - x
-                    let (diff, borrow) = self.v[y].overflowing_sub(self.v[x]);
-                    self.v[x] = diff;
-                    self.v[0xf] = !borrow as u8;
-                }
-                0xe => {
-                    let shift = self.v[y] << 1;
-                    let carry = if self.v[y] & 1 << 7 != 0 { 1 } else { 0 };
-                    self.v[x] = shift;
-                    self.v[0xf] = carry;
-                }
-                _ => err(),
-            },
-            0x9 => {
-                assert_eq!(n, 0);
+            }
+            Add(x, y) => {
+                let (sum, carry) = self.v[x].overflowing_add(self.v[y]);
+                self.v[x] = sum;
+                self.v[0xf] = carry as u8;
+            }
+            Sub(x, y) => {
+                let (diff, borrow) = self.v[x].overflowing_sub(self.v[y]);
+                self.v[x] = diff;
+                self.v[0xf] = !borrow as u8;
+            }
+            Shr(x, y) => {
+                let src = if self.quirks.shift_vy { y } else { x };
+                let shift = self.v[src] >> 1;
+                let carry = self.v[src] % 2;
+                self.v[x] = shift;
+                self.v[0xf] = carry;
+            }
+            SubN(x, y) => {
+                // y - x
+                let (diff, borrow) = self.v[y].overflowing_sub(self.v[x]);
+                self.v[x] = diff;
+                self.v[0xf] = !borrow as u8;
+            }
+            Shl(x, y) => {
+                let src = if self.quirks.shift_vy { y } else { x };
+                let shift = self.v[src] << 1;
+                let carry = if self.v[src] & 1 << 7 != 0 { 1 } else { 0 };
+                self.v[x] = shift;
+                self.v[0xf] = carry;
+            }
+            SkipNeReg(x, y) => {
                 if self.v[x] != self.v[y] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
-            0xa => self.i = addr,
-            0xb => self.pc = addr + self.v[0] as u16,
-            0xc => self.v[x] = self.io.get_random_byte() & k,
-            0xd => self.draw_sprite(x, y, n),
-            0xe => match k {
-                0x9e => {
-                    if self.io.is_key_pressed(self.v[x]) {
-                        self.pc += 2;
-                    }
+            LoadI(addr) => self.i = addr,
+            JumpOffset(addr) => {
+                // Quirk: either `Bnnn` (jump to nnn + v0) or `Bxnn` (jump to xnn + vx).
+                let x = (addr >> 8) as u8;
+                let offset = if self.quirks.jump_v0 {
+                    self.v[0]
+                } else {
+                    self.v[x]
+                };
+                self.pc = addr + offset as u16;
+            }
+            Rand(x, k) => {
+                let byte = match &mut self.vip_rng {
+                    Some(rng) => rng.next_byte(),
+                    None => self.io.get_random_byte(),
+                };
+                self.v[x] = byte & k;
+            }
+            Draw(x, y, n) => self.draw_sprite(x, y, n)?,
+            SkipKey(x) => {
+                if self.io.is_key_pressed(self.v[x] & 0xf) {
+                    self.skip();
                 }
-                0xa1 => {
-                    if !self.io.is_key_pressed(self.v[x]) {
-                        self.pc += 2;
-                    }
+            }
+            SkipNotKey(x) => {
+                if !self.io.is_key_pressed(self.v[x] & 0xf) {
+                    self.skip();
+                }
+            }
+            LoadILong(addr) => self.i = addr,
+            Plane(n) => self.screen.select_planes(n),
+            Audio => {
+                let mut pattern = [0; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.load(self.i as usize + offset)?;
+                }
+                self.io.write_audio_pattern(pattern);
+            }
+            GetDelay(x) => self.v[x] = self.dt,
+            WaitKey(x) => match self.io.key_released() {
+                Some(key) => self.v[x] = key,
+                None => {
+                    // Try again next time.
+                    self.pc = self.pc.wrapping_sub(instr.size());
+                    return Ok(Step::WaitingForKey);
                 }
-                _ => err(),
-            },
-            0xf => match k {
-                0x07 => self.v[x] = self.io.read_delay_timer(),
-                0x0a => self.v[x] = self.io.blocking_get_key(),
-                0x15 => self.io.write_delay_timer(self.v[x]),
-                0x18 => self.io.write_sound_timer(self.v[x]),
-                0x1e => self.i += self.v[x] as u16,
-                0x29 => self.i = Mem::sprite_offset(self.v[x]),
-                0x33 => {
-                    let bcd = bcd_from_u8(self.v[x]);
-                    for offset in 0..bcd.len() {
-                        self.mem[self.i + offset as u16] = bcd[offset];
-                    }
-                }
-                0x55 => {
-                    // Write registers to memory.
-                    for reg in 0..=x {
-                        self.mem[self.i + reg as u16] = self.v[reg];
-                    }
-                    self.i += x as u16 + 1;
-                }
-                0x65 => {
-                    // Read memory into registers.
-                    for reg in 0..=x {
-                        self.v[reg] = self.mem[self.i + reg as u16];
-                    }
-                    self.i += x as u16 + 1;
-                }
-                _ => err(),
             },
-            0x10.. => unreachable!(),
+            SetDelay(x) => self.dt = self.v[x],
+            SetSound(x) => self.st = self.v[x],
+            AddI(x) => self.i = self.i.wrapping_add(self.v[x] as u16),
+            Font(x) => self.i = Mem::sprite_offset(self.v[x] & 0xf),
+            BigFont(x) => self.i = Mem::big_sprite_offset(self.v[x] & 0xf),
+            Bcd(x) => {
+                let bcd = bcd_from_u8(self.v[x]);
+                for (offset, digit) in bcd.into_iter().enumerate() {
+                    self.store(self.i as usize + offset, digit)?;
+                }
+            }
+            Pitch(x) => self.io.write_pitch(self.v[x]),
+            Store(x) => {
+                // Write registers to memory.
+                for reg in 0..=x {
+                    self.store(self.i as usize + reg as usize, self.v[reg])?;
+                }
+                self.load_store_i(x);
+            }
+            Load(x) => {
+                // Read memory into registers.
+                for reg in 0..=x {
+                    self.v[reg] = self.load(self.i as usize + reg as usize)?;
+                }
+                self.load_store_i(x);
+            }
+            SaveFlags(x) => {
+                // Save registers to the flags.
+                for reg in 0..=x {
+                    self.flags[reg] = self.v[reg];
+                }
+            }
+            LoadFlags(x) => {
+                // Restore registers from the flags.
+                for reg in 0..=x {
+                    self.v[reg] = self.flags[reg];
+                }
+            }
         }
+
+        Ok(Step::Executed)
+    }
+
+    fn load(&mut self, addr: usize) -> Result<u8, Fault> {
+        let byte = self.mem.get(addr).ok_or(Fault::MemOutOfBounds(addr))?;
+        self.record_access(addr, 1, false);
+        Ok(byte)
     }
 
-    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) {
+    fn store(&mut self, addr: usize, value: u8) -> Result<(), Fault> {
+        let byte = self.mem.get_mut(addr).ok_or(Fault::MemOutOfBounds(addr))?;
+        *byte = value;
+        self.record_access(addr, 1, true);
+        Ok(())
+    }
+
+    /// Move `i` after `Fx55`/`Fx65`, depending on the quirk.
+    fn load_store_i(&mut self, x: u8) {
+        let step = match self.quirks.load_store_i {
+            LoadStoreI::Unchanged => 0,
+            LoadStoreI::PastLast => x as u16 + 1,
+            LoadStoreI::AddX => x as u16,
+        };
+        self.i = self.i.wrapping_add(step);
+    }
+
+    /// Add to `accesses`, merging with the previous one if it's adjacent.
+    fn record_access(&mut self, start: usize, len: usize, write: bool) {
+        if let Some(last) = self.accesses.last_mut() {
+            if last.write == write && last.start + last.len == start {
+                last.len += len;
+                return;
+            }
+        }
+        self.accesses.push(MemAccess { start, len, write });
+    }
+
+    /// Skip over the next instruction, which may be the 4-byte `F000 NNNN`.
+    fn skip(&mut self) {
+        let len = if self.read_word(self.pc) == Some(LONG_PREFIX) {
+            4
+        } else {
+            2
+        };
+        self.pc = self.pc.wrapping_add(len);
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.screen.set_hires(hires);
+        self.screen_dirty = true;
+    }
+
+    /// `Dxyn`. If `n` is 0, draw a 16x16 SUPER-CHIP sprite instead.
+    ///
+    /// With both XO-CHIP planes selected, the sprite data for the second plane
+    /// comes right after the data for the first.
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) -> Result<(), Fault> {
         assert!(x <= 0xf);
         assert!(y <= 0xf);
         assert!(n <= 0xf);
-        assert!(self.i + n as u16 <= Mem::LEN);
 
-        let xy = Point::from((self.v[x] as i8, self.v[y] as i8)).wrap();
-        let sprite = &self.mem[self.i..self.i + n as u16];
+        let len_per_plane = if n == 0 { 32 } else { n as usize };
+        let len = len_per_plane * self.screen.selected_planes().count();
+
+        let xy = Point::from((self.v[x] as i16, self.v[y] as i16)).wrap(self.screen.dims());
+        let start = self.i as usize;
+        let bytes = self
+            .mem
+            .slice(start, len)
+            .ok_or(Fault::MemOutOfBounds(start.max(self.mem.len())))?;
+        let sprite = if n == 0 {
+            Sprite::Wide(bytes)
+        } else {
+            Sprite::Narrow(bytes)
+        };
 
-        self.v[0xf] = self.io.draw_sprite(xy, sprite) as u8;
+        self.v[0xf] = self
+            .screen
+            .draw_sprite(xy, sprite, self.quirks.clip_sprites) as u8;
+        self.screen_dirty = true;
+        self.record_access(start, len, false);
+
+        // Quirk: the COSMAC VIP waits for the display interrupt after drawing.
+        if self.quirks.display_wait {
+            self.vblank_pending = true;
+        }
+
+        Ok(())
     }
 }
 
+/// The result of `Chip8::step`, or of the last step in a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// An instruction ran.
+    Executed,
+    /// `Fx0A` is waiting for a key to be released. It will check again on the
+    /// next step.
+    WaitingForKey,
+    /// The program exited, or is stuck jumping to itself.
+    Halted,
+    /// `Chip8::run_frame_until` was asked to stop before the next instruction.
+    Stopped,
+}
+
 /// Convert x to "big endian" binary coded decimal:
 /// [hundreds, tens, ones]
 fn bcd_from_u8(mut x: u8) -> [u8; 3] {
     // Start with [ones, tens, hundred], and then reverse.
     let mut digits = [0u8; 3];
-    for i in 0..3 {
-        digits[i] = x % 10;
+    for d in &mut digits {
+        *d = x % 10;
         x /= 10;
     }
 
@@ -226,11 +760,11 @@ fn bcd_from_u8(mut x: u8) -> [u8; 3] {
     digits
 }
 
-/// Big endian byte (and bit) order.
-fn nibbles_from_u16(x: u16) -> [u8; 4] {
-    let a = (x & 0xf000) >> 4 * 3;
-    let b = (x & 0x0f00) >> 4 * 2;
-    let c = (x & 0x00f0) >> 4 * 1;
-    let d = (x & 0x000f) >> 4 * 0;
-    [a, b, c, d].map(|n| n as u8)
+/// The registers `x..=y`, or in descending order if `x > y`.
+fn reg_range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
 }
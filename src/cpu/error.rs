@@ -0,0 +1,97 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+use super::mem::Mem;
+
+/// Something went wrong while loading or running a ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The ROM doesn't fit in memory after `Mem::ROM_START`.
+    RomTooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// `Quirks::mem_len` is too small to hold a ROM, or more than 64 KiB.
+    BadMemLen {
+        len: usize,
+    },
+    /// The program counter ran off the end of memory.
+    PcOutOfBounds {
+        pc: u16,
+    },
+    UnknownOpcode {
+        pc: u16,
+        opcode: u16,
+    },
+    /// Too many nested subroutine calls.
+    StackOverflow {
+        pc: u16,
+        opcode: u16,
+    },
+    /// Return with no subroutine to return from.
+    StackUnderflow {
+        pc: u16,
+        opcode: u16,
+    },
+    /// An instruction accessed memory past the end.
+    MemOutOfBounds {
+        pc: u16,
+        opcode: u16,
+        addr: usize,
+    },
+}
+
+impl Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::RomTooLarge { len, max } => {
+                write!(f, "ROM is too large: {len} bytes (max {max})")
+            }
+            Self::BadMemLen { len } => write!(
+                f,
+                "memory size must be from 0x{:x} to 0x{:x} bytes, not 0x{len:x}",
+                Mem::ROM_START,
+                Mem::XO_CHIP_LEN
+            ),
+            Self::PcOutOfBounds { pc } => write!(f, "pc out of bounds (pc=0x{pc:04x})"),
+            Self::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode 0x{opcode:04x} (pc=0x{pc:04x})")
+            }
+            Self::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow: 0x{opcode:04x} (pc=0x{pc:04x})")
+            }
+            Self::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow: 0x{opcode:04x} (pc=0x{pc:04x})")
+            }
+            Self::MemOutOfBounds { pc, opcode, addr } => write!(
+                f,
+                "memory access out of bounds at 0x{addr:04x}: 0x{opcode:04x} (pc=0x{pc:04x})"
+            ),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
+/// Why an instruction failed. This gets turned into a `Chip8Error` once we
+/// know which instruction it was.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Fault {
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemOutOfBounds(usize),
+}
+
+impl Fault {
+    pub(crate) fn at(self, pc: u16, opcode: u16) -> Chip8Error {
+        match self {
+            Self::UnknownOpcode => Chip8Error::UnknownOpcode { pc, opcode },
+            Self::StackOverflow => Chip8Error::StackOverflow { pc, opcode },
+            Self::StackUnderflow => Chip8Error::StackUnderflow { pc, opcode },
+            Self::MemOutOfBounds(addr) => Chip8Error::MemOutOfBounds { pc, opcode, addr },
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use std::fmt::{self, Display};
+
+/// The first word of the 4-byte XO-CHIP `F000 NNNN` instruction. The address
+/// `NNNN` is in the following word.
+pub const LONG_PREFIX: u16 = 0xf000;
+
+/// A decoded instruction. `x` and `y` are register numbers.
+///
+/// The `Display` impl uses the usual (Cowgod-style) mnemonics, extended with
+/// the SUPER-CHIP and XO-CHIP instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `00Cn`: `SCD n`
+    ScrollDown(u8),
+    /// `00E0`: `CLS`
+    Cls,
+    /// `00EE`: `RET`
+    Ret,
+    /// `00FB`: `SCR`
+    ScrollRight,
+    /// `00FC`: `SCL`
+    ScrollLeft,
+    /// `00FD`: `EXIT`
+    Exit,
+    /// `00FE`: `LOW`
+    Lores,
+    /// `00FF`: `HIGH`
+    Hires,
+    /// `1nnn`: `JP addr`
+    Jump(u16),
+    /// `2nnn`: `CALL addr`
+    Call(u16),
+    /// `3xkk`: `SE Vx, byte`
+    SkipEqImm(u8, u8),
+    /// `4xkk`: `SNE Vx, byte`
+    SkipNeImm(u8, u8),
+    /// `5xy0`: `SE Vx, Vy`
+    SkipEqReg(u8, u8),
+    /// `5xy2`: `SAVE Vx, Vy`
+    SaveRange(u8, u8),
+    /// `5xy3`: `LOAD Vx, Vy`
+    LoadRange(u8, u8),
+    /// `6xkk`: `LD Vx, byte`
+    LoadImm(u8, u8),
+    /// `7xkk`: `ADD Vx, byte`
+    AddImm(u8, u8),
+    /// `8xy0`: `LD Vx, Vy`
+    Move(u8, u8),
+    /// `8xy1`: `OR Vx, Vy`
+    Or(u8, u8),
+    /// `8xy2`: `AND Vx, Vy`
+    And(u8, u8),
+    /// `8xy3`: `XOR Vx, Vy`
+    Xor(u8, u8),
+    /// `8xy4`: `ADD Vx, Vy`
+    Add(u8, u8),
+    /// `8xy5`: `SUB Vx, Vy`
+    Sub(u8, u8),
+    /// `8xy6`: `SHR Vx, Vy`
+    Shr(u8, u8),
+    /// `8xy7`: `SUBN Vx, Vy`
+    SubN(u8, u8),
+    /// `8xyE`: `SHL Vx, Vy`
+    Shl(u8, u8),
+    /// `9xy0`: `SNE Vx, Vy`
+    SkipNeReg(u8, u8),
+    /// `Annn`: `LD I, addr`
+    LoadI(u16),
+    /// `Bnnn`: `JP V0, addr`
+    JumpOffset(u16),
+    /// `Cxkk`: `RND Vx, byte`
+    Rand(u8, u8),
+    /// `Dxyn`: `DRW Vx, Vy, n`
+    Draw(u8, u8, u8),
+    /// `Ex9E`: `SKP Vx`
+    SkipKey(u8),
+    /// `ExA1`: `SKNP Vx`
+    SkipNotKey(u8),
+    /// `F000 NNNN`: `LD I, long addr`
+    LoadILong(u16),
+    /// `Fn01`: `PLANE n`
+    Plane(u8),
+    /// `F002`: `AUDIO`
+    Audio,
+    /// `Fx07`: `LD Vx, DT`
+    GetDelay(u8),
+    /// `Fx0A`: `LD Vx, K`
+    WaitKey(u8),
+    /// `Fx15`: `LD DT, Vx`
+    SetDelay(u8),
+    /// `Fx18`: `LD ST, Vx`
+    SetSound(u8),
+    /// `Fx1E`: `ADD I, Vx`
+    AddI(u8),
+    /// `Fx29`: `LD F, Vx`
+    Font(u8),
+    /// `Fx30`: `LD HF, Vx`
+    BigFont(u8),
+    /// `Fx33`: `LD B, Vx`
+    Bcd(u8),
+    /// `Fx3A`: `PITCH Vx`
+    Pitch(u8),
+    /// `Fx55`: `LD [I], Vx`
+    Store(u8),
+    /// `Fx65`: `LD Vx, [I]`
+    Load(u8),
+    /// `Fx75`: `LD R, Vx`
+    SaveFlags(u8),
+    /// `Fx85`: `LD Vx, R`
+    LoadFlags(u8),
+}
+
+use Instruction::*;
+
+impl Instruction {
+    /// `None` if the opcode isn't a valid instruction.
+    ///
+    /// This also returns `None` for `LONG_PREFIX`, since the rest of that
+    /// instruction is in the following word. Use `LoadILong` directly instead.
+    pub fn decode(opcode: u16) -> Option<Self> {
+        let [op, x, y, n] = nibbles_from_u16(opcode);
+        let [_, k] = opcode.to_be_bytes();
+        let addr = opcode & 0x0fff;
+
+        let instr = match op {
+            0x0 => match opcode {
+                0x00c0..=0x00cf => ScrollDown(n),
+                0x00e0 => Cls,
+                0x00ee => Ret,
+                0x00fb => ScrollRight,
+                0x00fc => ScrollLeft,
+                0x00fd => Exit,
+                0x00fe => Lores,
+                0x00ff => Hires,
+                _ => return None,
+            },
+            0x1 => Jump(addr),
+            0x2 => Call(addr),
+            0x3 => SkipEqImm(x, k),
+            0x4 => SkipNeImm(x, k),
+            0x5 => match n {
+                0x0 => SkipEqReg(x, y),
+                0x2 => SaveRange(x, y),
+                0x3 => LoadRange(x, y),
+                _ => return None,
+            },
+            0x6 => LoadImm(x, k),
+            0x7 => AddImm(x, k),
+            0x8 => match n {
+                0x0 => Move(x, y),
+                0x1 => Or(x, y),
+                0x2 => And(x, y),
+                0x3 => Xor(x, y),
+                0x4 => Add(x, y),
+                0x5 => Sub(x, y),
+                0x6 => Shr(x, y),
+                0x7 => SubN(x, y),
+                0xe => Shl(x, y),
+                _ => return None,
+            },
+            0x9 if n == 0 => SkipNeReg(x, y),
+            0x9 => return None,
+            0xa => LoadI(addr),
+            0xb => JumpOffset(addr),
+            0xc => Rand(x, k),
+            0xd => Draw(x, y, n),
+            0xe => match k {
+                0x9e => SkipKey(x),
+                0xa1 => SkipNotKey(x),
+                _ => return None,
+            },
+            0xf => match k {
+                0x01 if x <= 0b11 => Plane(x),
+                0x02 if x == 0 => Audio,
+                0x07 => GetDelay(x),
+                0x0a => WaitKey(x),
+                0x15 => SetDelay(x),
+                0x18 => SetSound(x),
+                0x1e => AddI(x),
+                0x29 => Font(x),
+                0x30 => BigFont(x),
+                0x33 => Bcd(x),
+                0x3a => Pitch(x),
+                0x55 => Store(x),
+                0x65 => Load(x),
+                0x75 => SaveFlags(x),
+                0x85 => LoadFlags(x),
+                _ => return None,
+            },
+            0x10.. => unreachable!(),
+        };
+        Some(instr)
+    }
+
+    /// The opcode with its operands as letters, e.g. `8xy4` or `Dxyn`, for
+    /// grouping instructions by kind.
+    pub fn pattern(self) -> &'static str {
+        match self {
+            ScrollDown(_) => "00Cn",
+            Cls => "00E0",
+            Ret => "00EE",
+            ScrollRight => "00FB",
+            ScrollLeft => "00FC",
+            Exit => "00FD",
+            Lores => "00FE",
+            Hires => "00FF",
+            Jump(_) => "1nnn",
+            Call(_) => "2nnn",
+            SkipEqImm(..) => "3xkk",
+            SkipNeImm(..) => "4xkk",
+            SkipEqReg(..) => "5xy0",
+            SaveRange(..) => "5xy2",
+            LoadRange(..) => "5xy3",
+            LoadImm(..) => "6xkk",
+            AddImm(..) => "7xkk",
+            Move(..) => "8xy0",
+            Or(..) => "8xy1",
+            And(..) => "8xy2",
+            Xor(..) => "8xy3",
+            Add(..) => "8xy4",
+            Sub(..) => "8xy5",
+            Shr(..) => "8xy6",
+            SubN(..) => "8xy7",
+            Shl(..) => "8xyE",
+            SkipNeReg(..) => "9xy0",
+            LoadI(_) => "Annn",
+            JumpOffset(_) => "Bnnn",
+            Rand(..) => "Cxkk",
+            Draw(..) => "Dxyn",
+            SkipKey(_) => "Ex9E",
+            SkipNotKey(_) => "ExA1",
+            LoadILong(_) => "F000",
+            Plane(_) => "Fn01",
+            Audio => "F002",
+            GetDelay(_) => "Fx07",
+            WaitKey(_) => "Fx0A",
+            SetDelay(_) => "Fx15",
+            SetSound(_) => "Fx18",
+            AddI(_) => "Fx1E",
+            Font(_) => "Fx29",
+            BigFont(_) => "Fx30",
+            Bcd(_) => "Fx33",
+            Pitch(_) => "Fx3A",
+            Store(_) => "Fx55",
+            Load(_) => "Fx65",
+            SaveFlags(_) => "Fx75",
+            LoadFlags(_) => "Fx85",
+        }
+    }
+
+    /// The first (or only) word of the instruction.
+    ///
+    /// For `LoadILong`, this is `LONG_PREFIX`, and the address goes in the
+    /// following word; see `to_bytes`.
+    ///
+    /// `None` if a field doesn't fit in the opcode, e.g. a register past `VF`
+    /// or an address past 0xfff.
+    pub fn encode(self) -> Option<u16> {
+        let nibble = |n: u8| (n <= 0xf).then_some(n as u16);
+        let addr12 = |addr: u16| (addr <= 0xfff).then_some(addr);
+        let xy =
+            |op: u16, x: u8, y: u8, n: u16| Some(op << 12 | nibble(x)? << 8 | nibble(y)? << 4 | n);
+        let xk = |op: u16, x: u8, k: u8| Some(op << 12 | nibble(x)? << 8 | k as u16);
+        let fx = |x: u8, k: u16| Some(0xf000 | nibble(x)? << 8 | k);
+
+        let opcode = match self {
+            ScrollDown(n) => 0x00c0 | nibble(n)?,
+            Cls => 0x00e0,
+            Ret => 0x00ee,
+            ScrollRight => 0x00fb,
+            ScrollLeft => 0x00fc,
+            Exit => 0x00fd,
+            Lores => 0x00fe,
+            Hires => 0x00ff,
+            Jump(addr) => 0x1000 | addr12(addr)?,
+            Call(addr) => 0x2000 | addr12(addr)?,
+            SkipEqImm(x, k) => xk(0x3, x, k)?,
+            SkipNeImm(x, k) => xk(0x4, x, k)?,
+            SkipEqReg(x, y) => xy(0x5, x, y, 0x0)?,
+            SaveRange(x, y) => xy(0x5, x, y, 0x2)?,
+            LoadRange(x, y) => xy(0x5, x, y, 0x3)?,
+            LoadImm(x, k) => xk(0x6, x, k)?,
+            AddImm(x, k) => xk(0x7, x, k)?,
+            Move(x, y) => xy(0x8, x, y, 0x0)?,
+            Or(x, y) => xy(0x8, x, y, 0x1)?,
+            And(x, y) => xy(0x8, x, y, 0x2)?,
+            Xor(x, y) => xy(0x8, x, y, 0x3)?,
+            Add(x, y) => xy(0x8, x, y, 0x4)?,
+            Sub(x, y) => xy(0x8, x, y, 0x5)?,
+            Shr(x, y) => xy(0x8, x, y, 0x6)?,
+            SubN(x, y) => xy(0x8, x, y, 0x7)?,
+            Shl(x, y) => xy(0x8, x, y, 0xe)?,
+            SkipNeReg(x, y) => xy(0x9, x, y, 0x0)?,
+            LoadI(addr) => 0xa000 | addr12(addr)?,
+            JumpOffset(addr) => 0xb000 | addr12(addr)?,
+            Rand(x, k) => xk(0xc, x, k)?,
+            Draw(x, y, n) => xy(0xd, x, y, nibble(n)?)?,
+            SkipKey(x) => xk(0xe, x, 0x9e)?,
+            SkipNotKey(x) => xk(0xe, x, 0xa1)?,
+            LoadILong(_) => LONG_PREFIX,
+            Plane(n) if n <= 0b11 => fx(n, 0x01)?,
+            Plane(_) => return None,
+            Audio => 0xf002,
+            GetDelay(x) => fx(x, 0x07)?,
+            WaitKey(x) => fx(x, 0x0a)?,
+            SetDelay(x) => fx(x, 0x15)?,
+            SetSound(x) => fx(x, 0x18)?,
+            AddI(x) => fx(x, 0x1e)?,
+            Font(x) => fx(x, 0x29)?,
+            BigFont(x) => fx(x, 0x30)?,
+            Bcd(x) => fx(x, 0x33)?,
+            Pitch(x) => fx(x, 0x3a)?,
+            Store(x) => fx(x, 0x55)?,
+            Load(x) => fx(x, 0x65)?,
+            SaveFlags(x) => fx(x, 0x75)?,
+            LoadFlags(x) => fx(x, 0x85)?,
+        };
+        Some(opcode)
+    }
+
+    /// The full encoding, big endian. `None` if it doesn't `encode`.
+    pub fn to_bytes(self) -> Option<Vec<u8>> {
+        let mut bytes = self.encode()?.to_be_bytes().to_vec();
+        if let LoadILong(addr) = self {
+            bytes.extend(addr.to_be_bytes());
+        }
+        Some(bytes)
+    }
+
+    /// Size in bytes: 4 for `LoadILong`, otherwise 2.
+    pub fn size(self) -> u16 {
+        match self {
+            LoadILong(_) => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ScrollDown(n) => write!(f, "SCD {n}"),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Lores => write!(f, "LOW"),
+            Hires => write!(f, "HIGH"),
+            Jump(addr) => write!(f, "JP 0x{addr:03x}"),
+            Call(addr) => write!(f, "CALL 0x{addr:03x}"),
+            SkipEqImm(x, k) => write!(f, "SE V{x:X}, 0x{k:02x}"),
+            SkipNeImm(x, k) => write!(f, "SNE V{x:X}, 0x{k:02x}"),
+            SkipEqReg(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            SaveRange(x, y) => write!(f, "SAVE V{x:X}, V{y:X}"),
+            LoadRange(x, y) => write!(f, "LOAD V{x:X}, V{y:X}"),
+            LoadImm(x, k) => write!(f, "LD V{x:X}, 0x{k:02x}"),
+            AddImm(x, k) => write!(f, "ADD V{x:X}, 0x{k:02x}"),
+            Move(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            Add(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Sub(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Shr(x, y) => write!(f, "SHR V{x:X}, V{y:X}"),
+            SubN(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Shl(x, y) => write!(f, "SHL V{x:X}, V{y:X}"),
+            SkipNeReg(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            LoadI(addr) => write!(f, "LD I, 0x{addr:03x}"),
+            JumpOffset(addr) => write!(f, "JP V0, 0x{addr:03x}"),
+            Rand(x, k) => write!(f, "RND V{x:X}, 0x{k:02x}"),
+            Draw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            SkipKey(x) => write!(f, "SKP V{x:X}"),
+            SkipNotKey(x) => write!(f, "SKNP V{x:X}"),
+            LoadILong(addr) => write!(f, "LD I, long 0x{addr:04x}"),
+            Plane(n) => write!(f, "PLANE {n}"),
+            Audio => write!(f, "AUDIO"),
+            GetDelay(x) => write!(f, "LD V{x:X}, DT"),
+            WaitKey(x) => write!(f, "LD V{x:X}, K"),
+            SetDelay(x) => write!(f, "LD DT, V{x:X}"),
+            SetSound(x) => write!(f, "LD ST, V{x:X}"),
+            AddI(x) => write!(f, "ADD I, V{x:X}"),
+            Font(x) => write!(f, "LD F, V{x:X}"),
+            BigFont(x) => write!(f, "LD HF, V{x:X}"),
+            Bcd(x) => write!(f, "LD B, V{x:X}"),
+            Pitch(x) => write!(f, "PITCH V{x:X}"),
+            Store(x) => write!(f, "LD [I], V{x:X}"),
+            Load(x) => write!(f, "LD V{x:X}, [I]"),
+            SaveFlags(x) => write!(f, "LD R, V{x:X}"),
+            LoadFlags(x) => write!(f, "LD V{x:X}, R"),
+        }
+    }
+}
+
+/// Big endian byte (and bit) order.
+fn nibbles_from_u16(x: u16) -> [u8; 4] {
+    let a = (x & 0xf000) >> 12;
+    let b = (x & 0x0f00) >> 8;
+    let c = (x & 0x00f0) >> 4;
+    let d = x & 0x000f;
+    [a, b, c, d].map(|n| n as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for opcode in 0..=u16::MAX {
+            if let Some(instr) = Instruction::decode(opcode) {
+                assert_eq!(instr.encode(), Some(opcode), "{instr:?}");
+            }
+        }
+
+        let instr = LoadILong(0xbeef);
+        assert_eq!(instr.encode(), Some(LONG_PREFIX));
+        assert_eq!(instr.to_bytes(), Some(vec![0xf0, 0x00, 0xbe, 0xef]));
+        assert_eq!(Instruction::decode(LONG_PREFIX), None);
+    }
+
+    #[test]
+    fn out_of_range() {
+        for instr in [
+            ScrollDown(0x10),
+            Jump(0x1000),
+            Call(0x1000),
+            LoadI(0x1000),
+            JumpOffset(0x1000),
+            LoadImm(0x10, 0),
+            Move(0x10, 0),
+            Move(0, 0x10),
+            Draw(0, 0, 0x10),
+            Plane(0b100),
+            Store(0x10),
+        ] {
+            assert_eq!(instr.encode(), None, "{instr:?}");
+            assert_eq!(instr.to_bytes(), None, "{instr:?}");
+        }
+    }
+}
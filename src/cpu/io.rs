@@ -1,42 +1,60 @@
 use std::fmt::Debug;
 
-use super::Point;
+use super::{
+    screen::Screen,
+    state::{StateError, StateReader, StateWriter},
+};
 
-/// Nanosecond duration for the delay timer and sound timer "ticks".
-///
-/// Frequency: 60 Hz.
+/// Nanosecond duration of a 60 Hz frame, which is also how often the delay
+/// timer and sound timer tick.
 pub const TIME_BETWEEN_TICKS_NS: u64 = 10_u64.pow(9) / 60;
 
 /// The input/output methods needed by the CHIP-8 interpreter.
+///
+/// The interpreter keeps track of the screen and the timers itself, so a
+/// backend only has to show pixels and report keys.
 pub trait Chip8Io: Debug {
-    /// This method gets called periodically (currently after every instruction).
+    /// This method gets called once at the end of every frame.
     ///
     /// You can use it to perform state updates, e.g. poll for keyboard input, etc.
     fn update(&mut self) {}
 
-    fn clear_screen(&mut self);
+    /// Called by `Chip8::run` between frames. A real-time backend should wait
+    /// here until it's time for the next one. The default implementation
+    /// returns right away.
+    fn wait_for_frame(&mut self) {}
 
-    fn get_random_byte(&mut self) -> u8;
+    /// Show the screen. Called at the end of each frame where it changed.
+    fn present(&mut self, screen: &Screen);
 
-    /// Quirk: wait for the "display interrupt" (60 Hz) before returning.
-    fn draw_sprite(&mut self, pos: Point, sprite: &[u8]) -> DrawSprite;
+    fn get_random_byte(&mut self) -> u8;
 
     /// Is the given key currently pressed? Keycodes are `0x0..=0xf`.
     fn is_key_pressed(&mut self, k: u8) -> bool;
 
-    /// Block until any key gets pressed. Return that keycode.
+    /// Was any key released during the most recent `update`? If so, return
+    /// that keycode.
     ///
-    /// Quirk: this should wait until some key gets *released*.
-    fn blocking_get_key(&mut self) -> u8;
+    /// Quirk: `Fx0A` waits until some key gets *released*, not pressed.
+    fn key_released(&mut self) -> Option<u8>;
 
-    fn read_delay_timer(&mut self) -> u8;
+    /// Set the XO-CHIP 1-bit audio pattern, played while the sound timer is
+    /// non-zero. The default implementation ignores it.
+    fn write_audio_pattern(&mut self, _pattern: [u8; 16]) {}
 
-    fn write_delay_timer(&mut self, value: u8);
+    /// Set the XO-CHIP audio playback rate. The default implementation ignores it.
+    fn write_pitch(&mut self, _pitch: u8) {}
 
-    fn write_sound_timer(&mut self, value: u8);
-}
+    /// Append any backend state to a save state. The default implementation
+    /// writes nothing.
+    fn save_state(&self, _w: &mut StateWriter) {}
 
-pub enum DrawSprite {
-    NoCollision,
-    Collision,
+    /// Restore the state written by `save_state`. It's the last thing in the
+    /// save state, so read all of it, and check that nothing is left with
+    /// `StateReader::finish` before changing anything.
+    ///
+    /// On error, the backend should be left unchanged.
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.finish()
+    }
 }
@@ -1,32 +1,94 @@
-use std::{
-    fmt::{self, Debug},
-    ops::{Index, IndexMut, Range},
-};
+use std::fmt::{self, Debug};
 
-use super::debug::{self, DebugHexByte};
+use super::{
+    debug::{self, DebugHexByte},
+    error::Chip8Error,
+    state::{StateError, StateReader, StateWriter},
+};
 
 #[derive(Clone)]
 pub struct Mem {
-    bytes: Box<[u8; Self::LEN as usize]>,
+    bytes: Box<[u8]>,
 }
 
 impl Mem {
-    pub const LEN: u16 = 4 * 1024;
+    /// Memory size of the original CHIP-8 (and SUPER-CHIP).
+    pub const CHIP8_LEN: usize = 4 * 1024;
+    /// XO-CHIP extends memory to the whole 16-bit address space.
+    pub const XO_CHIP_LEN: usize = 64 * 1024;
+
     pub const ROM_START: u16 = 0x0200;
+    const BIG_DIGITS_START: u16 = DIGITS.len() as u16 * 5;
 
     /// Load a ROM into memory, starting at offset 0x200.
-    pub fn new(rom: &[u8]) -> Self {
+    ///
+    /// `len` is the total size of memory, from `ROM_START` to `XO_CHIP_LEN`.
+    pub fn new(rom: &[u8], len: usize) -> Result<Self, Chip8Error> {
         let rom_start = Self::ROM_START as usize;
-        assert!(rom_start + rom.len() <= Self::LEN as usize);
+        if !(rom_start..=Self::XO_CHIP_LEN).contains(&len) {
+            return Err(Chip8Error::BadMemLen { len });
+        }
+        if rom_start + rom.len() > len {
+            return Err(Chip8Error::RomTooLarge {
+                len: rom.len(),
+                max: len - rom_start,
+            });
+        }
 
-        let mut bytes = Box::new([0u8; Self::LEN as usize]);
+        let mut bytes = vec![0u8; len].into_boxed_slice();
         bytes[rom_start..][..rom.len()].copy_from_slice(rom);
 
         // Load built-in sprites into memory starting at offset 0x000.
         let digits: Vec<_> = DIGITS.into_iter().flatten().collect();
         bytes[..digits.len()].copy_from_slice(&digits);
 
-        Self { bytes }
+        // The large (SUPER-CHIP) digits come right after.
+        let big_digits: Vec<_> = BIG_DIGITS.into_iter().flatten().collect();
+        bytes[Self::BIG_DIGITS_START as usize..][..big_digits.len()].copy_from_slice(&big_digits);
+
+        Ok(Self { bytes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// `None` if out of bounds.
+    pub fn get(&self, addr: usize) -> Option<u8> {
+        self.bytes.get(addr).copied()
+    }
+
+    /// `None` if out of bounds.
+    pub fn get_mut(&mut self, addr: usize) -> Option<&mut u8> {
+        self.bytes.get_mut(addr)
+    }
+
+    /// The `len` bytes starting at `start`, or `None` if that goes out of bounds.
+    pub fn slice(&self, start: usize, len: usize) -> Option<&[u8]> {
+        self.bytes.get(start..start.checked_add(len)?)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    pub fn save(&self, w: &mut StateWriter) {
+        w.u32(self.bytes.len() as u32);
+        w.bytes(&self.bytes);
+    }
+
+    /// `len` is the size of the memory being replaced, which the saved one
+    /// has to match.
+    pub fn load(r: &mut StateReader, len: usize) -> Result<Self, StateError> {
+        if r.u32()? as usize != len {
+            return Err(StateError::Invalid("memory is the wrong size"));
+        }
+        let bytes = r.bytes(len)?.into();
+        Ok(Self { bytes })
     }
 
     /// Where in memory is the sprite for this hex digit?
@@ -36,6 +98,29 @@ impl Mem {
         // We load sprites starting at 0, and they're each 5 bytes wide.
         hex_digit as u16 * 5
     }
+
+    /// Where in memory is the large (8x10) sprite for this hex digit?
+    pub const fn big_sprite_offset(hex_digit: u8) -> u16 {
+        assert!(hex_digit <= 0xf);
+
+        // These are each 10 bytes wide.
+        Self::BIG_DIGITS_START + hex_digit as u16 * 10
+    }
+}
+
+/// A run of bytes that an instruction read or wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    pub start: usize,
+    pub len: usize,
+    pub write: bool,
+}
+
+impl MemAccess {
+    /// Does this touch any of the bytes `start..=end`?
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start <= end && start < self.start + self.len
+    }
 }
 
 /// Bitmaps for the built-in hex digit sprites.
@@ -58,27 +143,28 @@ const DIGITS: [[u8; 5]; 16] = [
     [0xF0, 0x80, 0xF0, 0x80, 0x80],
 ];
 
-impl Index<u16> for Mem {
-    type Output = u8;
-
-    fn index(&self, index: u16) -> &Self::Output {
-        &self.bytes[index as usize]
-    }
-}
-
-impl IndexMut<u16> for Mem {
-    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
-        &mut self.bytes[index as usize]
-    }
-}
-
-impl Index<Range<u16>> for Mem {
-    type Output = [u8];
-
-    fn index(&self, index: Range<u16>) -> &Self::Output {
-        &self.bytes[index.start as usize..index.end as usize]
-    }
-}
+/// Bitmaps for the SUPER-CHIP large hex digit sprites.
+///
+/// The original SUPER-CHIP only has `0` through `9`; `A` through `F` are the
+/// ones used by Octo.
+const BIG_DIGITS: [[u8; 10]; 16] = [
+    [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C],
+    [0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C],
+    [0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF],
+    [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C],
+    [0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C],
+    [0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C],
+    [0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60],
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C],
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C],
+    [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+    [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+    [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0],
+];
 
 impl Debug for Mem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -113,6 +199,16 @@ impl Debug for Mem {
 }
 
 impl Mem {
+    /// A few rows of the hexdump from the alternate `Debug` format, starting
+    /// with the row containing `addr`.
+    pub fn hexdump(&self, addr: usize, rows: usize) -> HexDump<'_> {
+        HexDump {
+            mem: self,
+            first_row: addr / 16,
+            rows,
+        }
+    }
+
     /// Helper for <Mem as Debug>::fmt
     fn debug_compact(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Show up to and including the last non-zero value.
@@ -124,3 +220,22 @@ impl Mem {
         f.debug_list().entries(entries).finish()
     }
 }
+
+/// See `Mem::hexdump`.
+pub struct HexDump<'a> {
+    mem: &'a Mem,
+    first_row: usize,
+    rows: usize,
+}
+
+impl Debug for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = self.mem.bytes.chunks(16).enumerate();
+        for (i, line) in lines.skip(self.first_row).take(self.rows) {
+            write!(f, "{i:02x}0: ")?;
+            debug::write_row(f, line.try_into().unwrap())?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
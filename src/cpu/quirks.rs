@@ -0,0 +1,135 @@
+use super::mem::Mem;
+
+/// Behaviors that differ between CHIP-8 interpreters.
+///
+/// ROMs are usually written against one particular interpreter, so pick the
+/// preset matching the one a ROM was made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6`/`8xyE` shift `vy` and store the result in `vx`. Otherwise they
+    /// shift `vx` in place.
+    pub shift_vy: bool,
+    /// What `Fx55`/`Fx65` do to `i` afterwards.
+    pub load_store_i: LoadStoreI,
+    /// `8xy1`/`8xy2`/`8xy3` reset `vf` to 0.
+    pub logic_resets_vf: bool,
+    /// `Bnnn` jumps to `nnn + v0`. Otherwise it's `Bxnn`, which jumps to
+    /// `xnn + vx`.
+    pub jump_v0: bool,
+    /// Sprites drawn at the edge of the screen are clipped. Otherwise they
+    /// wrap around to the other side.
+    pub clip_sprites: bool,
+    /// `Dxyn` waits for the next 60 Hz "display interrupt" before continuing.
+    pub display_wait: bool,
+    /// Total size of memory, in bytes.
+    pub mem_len: usize,
+}
+
+/// What `Fx55`/`Fx65` do to `i` once they've stored or loaded `v0` to `vx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStoreI {
+    /// `i` doesn't change.
+    Unchanged,
+    /// `i` ends up just past the last register: `i += x + 1`.
+    PastLast,
+    /// CHIP-48 is off by one: `i += x`.
+    AddX,
+}
+
+impl Quirks {
+    /// The original interpreter.
+    pub const COSMAC_VIP: Self = Self {
+        shift_vy: true,
+        load_store_i: LoadStoreI::PastLast,
+        logic_resets_vf: true,
+        jump_v0: true,
+        clip_sprites: true,
+        display_wait: true,
+        mem_len: Mem::CHIP8_LEN,
+    };
+
+    /// The HP-48 calculator interpreter.
+    pub const CHIP_48: Self = Self {
+        shift_vy: false,
+        load_store_i: LoadStoreI::AddX,
+        logic_resets_vf: false,
+        jump_v0: false,
+        clip_sprites: true,
+        display_wait: false,
+        mem_len: Mem::CHIP8_LEN,
+    };
+
+    /// SUPER-CHIP 1.1, which inherits most of its behavior from CHIP-48, but
+    /// leaves `i` alone after `Fx55`/`Fx65`.
+    pub const SCHIP_1_1: Self = Self {
+        shift_vy: false,
+        load_store_i: LoadStoreI::Unchanged,
+        logic_resets_vf: false,
+        jump_v0: false,
+        clip_sprites: true,
+        display_wait: false,
+        mem_len: Mem::CHIP8_LEN,
+    };
+
+    /// Octo's XO-CHIP, which mostly goes back to the COSMAC VIP behavior.
+    pub const XO_CHIP: Self = Self {
+        shift_vy: true,
+        load_store_i: LoadStoreI::PastLast,
+        logic_resets_vf: false,
+        jump_v0: true,
+        clip_sprites: false,
+        display_wait: false,
+        mem_len: Mem::XO_CHIP_LEN,
+    };
+
+    /// Names accepted by `Quirks::preset`.
+    pub const PRESET_NAMES: [&'static str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+    /// Look up a preset by name (see `PRESET_NAMES`).
+    pub fn preset(name: &str) -> Option<Self> {
+        let quirks = match name {
+            "vip" => Self::COSMAC_VIP,
+            "chip48" => Self::CHIP_48,
+            "schip" => Self::SCHIP_1_1,
+            "xochip" => Self::XO_CHIP,
+            _ => return None,
+        };
+        Some(quirks)
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::COSMAC_VIP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Chip8, Chip8Error, HeadlessIo};
+
+    #[test]
+    fn load_store_i() {
+        let rom = asm::assemble("LD I, 0x300\nLD [I], V2").unwrap();
+        for (name, i) in [("vip", 0x303), ("chip48", 0x302), ("schip", 0x300)] {
+            let quirks = Quirks::preset(name).unwrap();
+            let mut chip8 = Chip8::new(&rom, quirks, HeadlessIo::new(0)).unwrap();
+            chip8.step().unwrap();
+            chip8.step().unwrap();
+            assert_eq!(chip8.i(), i, "{name}");
+        }
+    }
+
+    #[test]
+    fn bad_mem_len() {
+        for len in [0, 0x1ff, Mem::XO_CHIP_LEN + 1] {
+            let quirks = Quirks {
+                mem_len: len,
+                ..Quirks::default()
+            };
+            let result = Chip8::new(&[], quirks, HeadlessIo::new(0));
+            assert_eq!(result.err(), Some(Chip8Error::BadMemLen { len }));
+        }
+    }
+}
@@ -3,9 +3,12 @@ use std::{
     ops::{Index, IndexMut},
 };
 
-use super::debug;
+use super::{
+    debug,
+    state::{StateError, StateReader, StateWriter},
+};
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Regs {
     regs: [u8; 16],
 }
@@ -14,6 +17,14 @@ impl Regs {
     pub fn new() -> Self {
         Self { regs: [0; 16] }
     }
+
+    pub fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.regs);
+    }
+
+    pub fn load(r: &mut StateReader) -> Result<Self, StateError> {
+        Ok(Self { regs: r.array()? })
+    }
 }
 
 impl Index<u8> for Regs {
@@ -0,0 +1,244 @@
+//! A history of recent save states, for stepping backwards in time.
+
+use std::collections::VecDeque;
+
+/// Zero bytes in a row that end a run in a `Delta`. Shorter gaps are cheaper
+/// to store inline than to start a new run.
+const MIN_GAP: usize = 8;
+
+/// A bounded ring buffer of snapshots from `Chip8::save_state`, newest last.
+///
+/// Only the newest snapshot is kept whole. Each older one is stored as a
+/// delta against the snapshot after it, which is usually tiny since most of
+/// memory doesn't change from one frame to the next. When the total size
+/// goes over the budget, the oldest snapshots are dropped.
+#[derive(Debug)]
+pub struct Rewind {
+    budget: usize,
+    newest: Option<Vec<u8>>,
+    /// `deltas[n]` turns snapshot `n + 1` back into snapshot `n`.
+    deltas: VecDeque<Delta>,
+    /// Bytes used by `newest` and `deltas`.
+    used: usize,
+}
+
+impl Rewind {
+    /// Keep roughly `budget` bytes of history. The newest snapshot is always
+    /// kept, even if it's bigger than the budget by itself.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            newest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    /// Record a new snapshot.
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        self.used += snapshot.len();
+        if let Some(prev) = self.newest.replace(snapshot) {
+            let delta = Delta::new(self.newest.as_ref().unwrap(), &prev);
+            self.used -= prev.len();
+            self.used += delta.size();
+            self.deltas.push_back(delta);
+        }
+
+        while self.used > self.budget {
+            let Some(oldest) = self.deltas.pop_front() else {
+                break;
+            };
+            self.used -= oldest.size();
+        }
+    }
+
+    /// Drop the newest snapshot, and return the one before it, which becomes
+    /// the newest. Returns `None` once there's no more history.
+    pub fn step_back(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        let newest = self.newest.as_mut().unwrap();
+
+        self.used -= delta.size() + newest.len();
+        delta.apply(newest);
+        self.used += newest.len();
+
+        Some(newest)
+    }
+
+    /// How many times `step_back` can succeed.
+    pub fn frames(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// Forget everything.
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+}
+
+/// The XOR of two snapshots, run-length encoded as a sequence of
+/// `(zeros to skip: u32, len: u32, [u8; len])`.
+#[derive(Debug)]
+struct Delta {
+    /// The length of the snapshot this produces.
+    len: usize,
+    runs: Vec<u8>,
+}
+
+impl Delta {
+    /// A delta that turns `from` into `to`.
+    fn new(from: &[u8], to: &[u8]) -> Self {
+        let xor: Vec<u8> = (0..from.len().max(to.len()))
+            .map(|n| from.get(n).unwrap_or(&0) ^ to.get(n).unwrap_or(&0))
+            .collect();
+
+        let mut runs = vec![];
+        let mut pos = 0;
+        while let Some(start) = xor[pos..].iter().position(|&b| b != 0) {
+            let start = pos + start;
+
+            let mut end = start;
+            let mut zeros = 0;
+            for (n, &b) in xor[start..].iter().enumerate() {
+                if b != 0 {
+                    end = start + n + 1;
+                    zeros = 0;
+                } else {
+                    zeros += 1;
+                    if zeros == MIN_GAP {
+                        break;
+                    }
+                }
+            }
+
+            runs.extend(((start - pos) as u32).to_be_bytes());
+            runs.extend(((end - start) as u32).to_be_bytes());
+            runs.extend(&xor[start..end]);
+            pos = end;
+        }
+
+        Self {
+            len: to.len(),
+            runs,
+        }
+    }
+
+    fn apply(&self, snapshot: &mut Vec<u8>) {
+        snapshot.resize(snapshot.len().max(self.len), 0);
+
+        let mut pos = 0;
+        let mut runs = &self.runs[..];
+        while let [s0, s1, s2, s3, l0, l1, l2, l3, rest @ ..] = runs {
+            let skip = u32::from_be_bytes([*s0, *s1, *s2, *s3]) as usize;
+            let len = u32::from_be_bytes([*l0, *l1, *l2, *l3]) as usize;
+            let (bytes, rest) = rest.split_at(len);
+
+            pos += skip;
+            for (dst, src) in snapshot[pos..pos + len].iter_mut().zip(bytes) {
+                *dst ^= src;
+            }
+            pos += len;
+            runs = rest;
+        }
+
+        snapshot.truncate(self.len);
+    }
+
+    fn size(&self) -> usize {
+        self.runs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Snapshots that mostly stay the same, with a few changes of each size,
+    /// some of them growing or shrinking.
+    fn snapshots(n: usize) -> Vec<Vec<u8>> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut snapshot = vec![0u8; 300];
+        let mut all = vec![];
+        for _ in 0..n {
+            for _ in 0..rng.gen_range(0..6) {
+                let start = rng.gen_range(0..snapshot.len());
+                let len = rng.gen_range(1..=2 * MIN_GAP).min(snapshot.len() - start);
+                rng.fill(&mut snapshot[start..start + len]);
+            }
+            match rng.gen_range(0..4) {
+                0 => snapshot.resize(snapshot.len() + rng.gen_range(1..40), 0xaa),
+                1 => snapshot.truncate(snapshot.len().saturating_sub(rng.gen_range(1..40))),
+                _ => {}
+            }
+            all.push(snapshot.clone());
+        }
+        all
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let cases: [(&[u8], &[u8]); 6] = [
+            (&[], &[]),
+            (&[1, 2, 3], &[1, 2, 3]),
+            (&[], &[1, 2, 3]),
+            (&[1, 2, 3], &[]),
+            (&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2], &[0; 4]),
+            (&[0; 4], &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]),
+        ];
+        for (from, to) in cases {
+            let mut snapshot = from.to_vec();
+            Delta::new(from, to).apply(&mut snapshot);
+            assert_eq!(snapshot, to, "{from:?} -> {to:?}");
+        }
+
+        let all = snapshots(50);
+        for pair in all.windows(2) {
+            let mut snapshot = pair[0].clone();
+            Delta::new(&pair[0], &pair[1]).apply(&mut snapshot);
+            assert_eq!(snapshot, pair[1]);
+        }
+    }
+
+    #[test]
+    fn step_back() {
+        let all = snapshots(50);
+        let mut rewind = Rewind::new(usize::MAX);
+        for snapshot in &all {
+            rewind.push(snapshot.clone());
+        }
+        assert_eq!(rewind.frames(), all.len() - 1);
+
+        for expected in all.iter().rev().skip(1) {
+            assert_eq!(rewind.step_back(), Some(&expected[..]));
+        }
+        assert_eq!(rewind.step_back(), None);
+        assert_eq!(rewind.frames(), 0);
+    }
+
+    #[test]
+    fn budget() {
+        let all = snapshots(50);
+        let mut rewind = Rewind::new(1000);
+        for snapshot in &all {
+            rewind.push(snapshot.clone());
+            assert!(rewind.used <= rewind.budget);
+        }
+        let frames = rewind.frames();
+        assert!(0 < frames && frames < all.len() - 1);
+
+        // What's left is the newest history, still intact.
+        for expected in all.iter().rev().skip(1).take(frames) {
+            assert_eq!(rewind.step_back(), Some(&expected[..]));
+        }
+        assert_eq!(rewind.step_back(), None);
+
+        rewind.clear();
+        assert_eq!(rewind.used, 0);
+        rewind.push(vec![0; 2000]);
+        rewind.push(vec![1; 2000]);
+        assert_eq!(rewind.frames(), 0);
+    }
+}
@@ -0,0 +1,111 @@
+use super::state::{StateError, StateReader, StateWriter};
+
+/// Stands in for the page of the interpreter's code that `VipRng` adds from.
+/// Like code, it has no particular pattern, and it never changes.
+const CODE_PAGE: [u8; 256] = code_page();
+
+/// Bytes from a fixed xorshift sequence.
+const fn code_page() -> [u8; 256] {
+    let mut page = [0; 256];
+    let mut x: u32 = 0x2545_f491;
+    let mut n = 0;
+    while n < page.len() {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        page[n] = (x >> 24) as u8;
+        n += 1;
+    }
+    page
+}
+
+/// A random number generator modeled on the one in the original COSMAC VIP
+/// CHIP-8 interpreter, for `Cxkk`.
+///
+/// The VIP kept a 16-bit seed in register `R9`. Each `Cxkk` bumped it, and
+/// read a byte of the interpreter's own code, at `0x100` plus `R9`'s low
+/// byte. It added that to `R9`'s high byte, rotated the sum right through
+/// the carry, and added the sum again. The result became both the random
+/// byte and `R9`'s new high byte. That's not very random, and some games are
+/// known to depend on it.
+///
+/// The interpreter isn't part of this crate, so by default `CODE_PAGE`
+/// stands in for that page, and the sequence differs from a real VIP's. Pass
+/// the real page to `with_code_page` to match one exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VipRng {
+    r9: u16,
+    code_page: [u8; 256],
+}
+
+impl VipRng {
+    pub fn new(seed: u16) -> Self {
+        Self::with_code_page(seed, CODE_PAGE)
+    }
+
+    /// Use `code_page` as bytes `0x100..0x200` of the interpreter.
+    pub fn with_code_page(seed: u16, code_page: [u8; 256]) -> Self {
+        Self {
+            r9: seed,
+            code_page,
+        }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+        let [hi, lo] = self.r9.to_be_bytes();
+
+        let (sum, carry) = self.code_page[lo as usize].overflowing_add(hi);
+        // The 1802's `SHRC`: shift right, with the carry going into bit 7.
+        let rotated = sum >> 1 | (carry as u8) << 7;
+        let hi = rotated.wrapping_add(sum);
+
+        self.r9 = u16::from_be_bytes([hi, lo]);
+        hi
+    }
+
+    /// The code page isn't saved. It's configuration, like the quirks.
+    pub fn save(&self, w: &mut StateWriter) {
+        w.u16(self.r9);
+    }
+
+    /// Restore the seed written by `save`, keeping our code page.
+    pub fn load(&self, r: &mut StateReader) -> Result<Self, StateError> {
+        Ok(Self {
+            r9: r.u16()?,
+            code_page: self.code_page,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence() {
+        // Worked by hand: each byte is the code byte plus the old high byte,
+        // rotated right through the carry, plus itself again. The third one
+        // carries (0x37 + 0xf0 = 0x127).
+        let page = std::array::from_fn(|n| n as u8);
+        let mut rng = VipRng::with_code_page(0x1234, page);
+        let bytes: Vec<u8> = (0..3).map(|_| rng.next_byte()).collect();
+        assert_eq!(bytes, [0x6a, 0xf0, 0xba]);
+        assert_eq!(rng.r9, 0xba37);
+    }
+
+    #[test]
+    fn state_keeps_code_page() {
+        let page = [0x55; 256];
+        let mut rng = VipRng::with_code_page(0xbeef, page);
+        rng.next_byte();
+
+        let mut w = StateWriter::default();
+        rng.save(&mut w);
+        let bytes = w.finish();
+        let loaded = VipRng::with_code_page(0, page)
+            .load(&mut StateReader::new(&bytes))
+            .unwrap();
+        assert_eq!(loaded, rng);
+    }
+}
@@ -1,31 +1,39 @@
-use std::ops::Add;
+use super::state::{StateError, StateReader, StateWriter};
+use std::{
+    fmt::{self, Debug},
+    ops::Add,
+};
 
-pub const DIMS: Point = Point { x: 64, y: 32 };
+/// Display dimensions in the standard "low resolution" mode.
+pub const LORES_DIMS: Point = Point { x: 64, y: 32 };
 
-#[derive(Debug, Clone, Copy)]
+/// Display dimensions in the SUPER-CHIP "high resolution" mode.
+pub const HIRES_DIMS: Point = Point { x: 128, y: 64 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Point {
-    pub x: i8,
-    pub y: i8,
+    pub x: i16,
+    pub y: i16,
 }
 
-impl From<(i8, i8)> for Point {
-    fn from((x, y): (i8, i8)) -> Self {
+impl From<(i16, i16)> for Point {
+    fn from((x, y): (i16, i16)) -> Self {
         Self { x, y }
     }
 }
 
 impl Point {
     #[must_use]
-    pub fn wrap(self) -> Self {
+    pub fn wrap(self, dims: Point) -> Self {
         Self {
-            x: self.x.rem_euclid(DIMS.x),
-            y: self.y.rem_euclid(DIMS.y),
+            x: self.x.rem_euclid(dims.x),
+            y: self.y.rem_euclid(dims.y),
         }
     }
 
-    pub fn in_bounds(self) -> bool {
-        let x = 0 <= self.x && self.x < DIMS.x;
-        let y = 0 <= self.y && self.y < DIMS.y;
+    pub fn in_bounds(self, dims: Point) -> bool {
+        let x = 0 <= self.x && self.x < dims.x;
+        let y = 0 <= self.y && self.y < dims.y;
         x && y
     }
 }
@@ -40,3 +48,288 @@ impl Add for Point {
         }
     }
 }
+
+/// Sprite data, as read from memory by the `Dxyn` instruction.
+#[derive(Debug, Clone, Copy)]
+pub enum Sprite<'a> {
+    /// 8 pixels wide, one byte per row.
+    Narrow(&'a [u8]),
+    /// SUPER-CHIP 16x16 sprite (`Dxy0`), two bytes per row.
+    Wide(&'a [u8]),
+}
+
+impl<'a> Sprite<'a> {
+    /// Width in pixels.
+    pub fn width(self) -> i16 {
+        match self {
+            Sprite::Narrow(_) => 8,
+            Sprite::Wide(_) => 16,
+        }
+    }
+
+    fn bytes(self) -> &'a [u8] {
+        match self {
+            Sprite::Narrow(bytes) | Sprite::Wide(bytes) => bytes,
+        }
+    }
+
+    fn with_bytes(self, bytes: &'a [u8]) -> Self {
+        match self {
+            Sprite::Narrow(_) => Sprite::Narrow(bytes),
+            Sprite::Wide(_) => Sprite::Wide(bytes),
+        }
+    }
+
+    /// Split the data for `num_planes` XO-CHIP display planes into one sprite
+    /// per plane. The data for each plane is stored one after the other.
+    pub fn split_planes(self, num_planes: usize) -> impl Iterator<Item = Sprite<'a>> {
+        let bytes = self.bytes();
+        let len = bytes.len() / num_planes.max(1);
+        bytes.chunks(len.max(1)).map(move |b| self.with_bytes(b))
+    }
+
+    /// Each row of pixels, packed into the high bits of a `u16`.
+    pub fn rows(self) -> impl Iterator<Item = u16> + 'a {
+        let bytes_per_row = match self {
+            Sprite::Narrow(_) => 1,
+            Sprite::Wide(_) => 2,
+        };
+
+        self.bytes().chunks(bytes_per_row).map(|row| {
+            let hi = row[0] as u16;
+            let lo = row.get(1).copied().unwrap_or(0) as u16;
+            (hi << 8) | lo
+        })
+    }
+}
+
+const WIDTH: usize = HIRES_DIMS.x as usize;
+const HEIGHT: usize = HIRES_DIMS.y as usize;
+
+const NUM_PLANES: usize = 2;
+
+type Plane = [[bool; WIDTH]; HEIGHT];
+
+const BLANK: Plane = [[false; WIDTH]; HEIGHT];
+
+/// The framebuffer. In low resolution mode, only the top-left 64x32 pixels
+/// are used.
+#[derive(Clone)]
+pub struct Screen {
+    /// XO-CHIP display planes. Plain CHIP-8 programs only use the first one.
+    planes: [Plane; NUM_PLANES],
+    /// Bitmask of the planes affected by drawing, clearing, and scrolling.
+    selected: u8,
+    hires: bool,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            planes: [BLANK; NUM_PLANES],
+            selected: 0b01,
+            hires: false,
+        }
+    }
+
+    /// Only clears the selected planes.
+    pub fn clear(&mut self) {
+        for plane in self.selected_planes() {
+            self.planes[plane] = BLANK;
+        }
+    }
+
+    /// Switching modes clears all planes.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.planes = [BLANK; NUM_PLANES];
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        assert!(planes <= 0b11);
+        self.selected = planes;
+    }
+
+    /// The current dimensions, depending on the resolution mode.
+    pub fn dims(&self) -> Point {
+        if self.hires {
+            HIRES_DIMS
+        } else {
+            LORES_DIMS
+        }
+    }
+
+    /// The color of a pixel: bit 0 is set if it's lit in the first plane, and
+    /// bit 1 if it's lit in the second plane.
+    pub fn pixel(&self, p: Point) -> u8 {
+        assert!(p.in_bounds(self.dims()));
+
+        let (x, y) = (p.x as usize, p.y as usize);
+        let lo = self.planes[0][y][x] as u8;
+        let hi = self.planes[1][y][x] as u8;
+        hi << 1 | lo
+    }
+
+    pub fn scroll_down(&mut self, n: u8) {
+        let height = self.dims().y as usize;
+        let n = (n as usize).min(height);
+
+        for plane in self.selected_planes() {
+            let rows = &mut self.planes[plane];
+            rows.copy_within(..height - n, n);
+            for row in &mut rows[..n] {
+                *row = [false; WIDTH];
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self) {
+        let width = self.dims().x as usize;
+        for plane in self.selected_planes() {
+            for row in &mut self.planes[plane] {
+                row.copy_within(4..width, 0);
+                row[width - 4..width].fill(false);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self) {
+        let width = self.dims().x as usize;
+        for plane in self.selected_planes() {
+            for row in &mut self.planes[plane] {
+                row.copy_within(..width - 4, 4);
+                row[..4].fill(false);
+            }
+        }
+    }
+
+    /// If more than one plane is selected, `sprite` contains the data for
+    /// each of them, one after the other.
+    ///
+    /// If `clip` is set, pixels past the edges are dropped; otherwise they wrap.
+    pub fn draw_sprite(&mut self, top_left: Point, sprite: Sprite, clip: bool) -> DrawSprite {
+        let mut collision = false;
+        let width = sprite.width();
+
+        let selected: Vec<_> = self.selected_planes().collect();
+        let layers = sprite.split_planes(selected.len());
+        for (plane, layer) in selected.into_iter().zip(layers) {
+            for (dy, row) in layer.rows().enumerate() {
+                for dx in 0..width {
+                    let mut pos = top_left + (dx, dy as i16).into();
+
+                    // Quirk: ignore pixels that would wrap.
+                    // This causes sprites drawn at the borders to be "clipped".
+                    if !pos.in_bounds(self.dims()) {
+                        if clip {
+                            continue;
+                        }
+                        pos = pos.wrap(self.dims());
+                    }
+
+                    let bit = 1 << (15 - dx);
+                    if row & bit != 0 && self.flip(plane, pos) {
+                        collision = true;
+                    }
+                }
+            }
+        }
+
+        if collision {
+            DrawSprite::Collision
+        } else {
+            DrawSprite::NoCollision
+        }
+    }
+
+    /// Return true if there's a collision.
+    fn flip(&mut self, plane: usize, p: Point) -> bool {
+        assert!(p.in_bounds(self.dims()));
+
+        let pixel = &mut self.planes[plane][p.y as usize][p.x as usize];
+        let was_high = *pixel;
+        *pixel ^= true;
+
+        was_high
+    }
+
+    /// Pixels are packed 8 per byte, row by row.
+    pub fn save(&self, w: &mut StateWriter) {
+        w.bool(self.hires);
+        w.u8(self.selected);
+        for plane in &self.planes {
+            for row in plane {
+                for pixels in row.chunks(8) {
+                    let byte = pixels.iter().fold(0, |acc, &p| acc << 1 | p as u8);
+                    w.u8(byte);
+                }
+            }
+        }
+    }
+
+    pub fn load(r: &mut StateReader) -> Result<Self, StateError> {
+        let hires = r.bool()?;
+        let selected = r.u8()?;
+        if selected > 0b11 {
+            return Err(StateError::Invalid("bad plane mask"));
+        }
+
+        let mut planes = [BLANK; NUM_PLANES];
+        for plane in &mut planes {
+            for row in plane {
+                for pixels in row.chunks_mut(8) {
+                    let byte = r.u8()?;
+                    for (i, p) in pixels.iter_mut().enumerate() {
+                        *p = byte & 0x80 >> i != 0;
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            planes,
+            selected,
+            hires,
+        })
+    }
+
+    pub(crate) fn selected_planes(&self) -> impl Iterator<Item = usize> {
+        let selected = self.selected;
+        (0..NUM_PLANES).filter(move |&plane| selected & 1 << plane != 0)
+    }
+}
+
+impl Debug for Screen {
+    /// Pixels are drawn as `.` (off), `#` (first plane), `+` (second plane),
+    /// or `@` (both planes).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dims = self.dims();
+
+        writeln!(f)?;
+        for y in 0..dims.y {
+            for x in 0..dims.x {
+                let c = match self.pixel((x, y).into()) {
+                    0 => '.',
+                    1 => '#',
+                    2 => '+',
+                    _ => '@',
+                };
+                write!(f, "{c}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawSprite {
+    NoCollision,
+    Collision,
+}
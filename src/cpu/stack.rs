@@ -1,3 +1,8 @@
+use super::{
+    error::Fault,
+    state::{StateError, StateReader, StateWriter},
+};
+
 #[derive(Debug, Clone)]
 pub struct Stack {
     values: Vec<u16>,
@@ -12,16 +17,40 @@ impl Stack {
         }
     }
 
-    /// Panics on overflow.
-    pub fn push(&mut self, value: u16) {
+    pub fn push(&mut self, value: u16) -> Result<(), Fault> {
         if self.values.len() >= CAPACITY {
-            panic!("stack overflow");
+            return Err(Fault::StackOverflow);
         }
         self.values.push(value);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u16, Fault> {
+        self.values.pop().ok_or(Fault::StackUnderflow)
     }
 
-    /// Panics on underflow.
-    pub fn pop(&mut self) -> u16 {
-        self.values.pop().unwrap()
+    /// The return addresses, innermost last.
+    pub fn as_slice(&self) -> &[u16] {
+        &self.values
+    }
+
+    pub fn save(&self, w: &mut StateWriter) {
+        w.u8(self.values.len() as u8);
+        for &value in &self.values {
+            w.u16(value);
+        }
+    }
+
+    pub fn load(r: &mut StateReader) -> Result<Self, StateError> {
+        let len = r.u8()? as usize;
+        if len > CAPACITY {
+            return Err(StateError::Invalid("stack is too deep"));
+        }
+
+        let mut values = Vec::with_capacity(CAPACITY);
+        for _ in 0..len {
+            values.push(r.u16()?);
+        }
+        Ok(Self { values })
     }
 }
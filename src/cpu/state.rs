@@ -0,0 +1,219 @@
+//! Helpers for the save state format.
+//!
+//! A save state starts with `MAGIC`, a version byte, and the ROM's
+//! `cheat::rom_id`, followed by the interpreter's state, and then whatever
+//! the `Chip8Io` backend writes. All numbers are big endian.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+pub const MAGIC: &[u8; 4] = b"CH8S";
+
+/// Bump this whenever the format changes.
+pub const VERSION: u8 = 4;
+
+/// A save state couldn't be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// Not a save state file.
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// Saved while running a different ROM.
+    WrongRom,
+    /// The data ended early.
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a save state"),
+            Self::UnsupportedVersion(v) => {
+                write!(f, "unsupported save state version {v} (expected {VERSION})")
+            }
+            Self::WrongRom => write!(f, "save state is for a different ROM"),
+            Self::Truncated => write!(f, "save state is truncated"),
+            Self::Invalid(what) => write!(f, "invalid save state: {what}"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    pub fn bool(&mut self, x: bool) {
+        self.u8(x as u8);
+    }
+
+    pub fn u16(&mut self, x: u16) {
+        self.bytes.extend(x.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, x: u32) {
+        self.bytes.extend(x.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, x: u64) {
+        self.bytes.extend(x.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("expected a bool")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    /// Check that all the data has been read.
+    pub fn finish(&self) -> Result<(), StateError> {
+        if !self.bytes.is_empty() {
+            return Err(StateError::Invalid("unexpected trailing data"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Chip8, HeadlessIo, Quirks};
+
+    /// Counts up in V0 and draws, so that most of the state changes.
+    fn machine(quirks: Quirks) -> Chip8<HeadlessIo> {
+        let rom = asm::assemble(
+            "
+            loop:   ADD V0, 1
+                    LD F, V0
+                    DRW V1, V1, 5
+                    CALL sub
+                    JP loop
+            sub:    LD DT, V0
+                    RET
+            ",
+        )
+        .unwrap();
+        let mut io = HeadlessIo::new(0);
+        io.tap_key(2, 0x5, 3);
+        Chip8::new(&rom, quirks, io).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut chip8 = machine(Quirks::default());
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+        let saved = chip8.save_state();
+
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+        assert_ne!(chip8.save_state(), saved);
+
+        chip8.load_state(&saved).unwrap();
+        assert_eq!(chip8.save_state(), saved);
+        assert_eq!(chip8.io().frame(), 3);
+    }
+
+    #[test]
+    fn rejects_bad_states() {
+        let mut chip8 = machine(Quirks::default());
+        chip8.run_frame().unwrap();
+        let saved = chip8.save_state();
+        chip8.run_frame().unwrap();
+        let before = chip8.save_state();
+
+        let mut trailing = saved.clone();
+        trailing.push(0);
+        assert_eq!(
+            chip8.load_state(&trailing),
+            Err(StateError::Invalid("unexpected trailing data"))
+        );
+        assert_eq!(
+            chip8.load_state(&saved[..saved.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(chip8.load_state(b"nope"), Err(StateError::BadMagic));
+
+        let mut other = Chip8::new(&[0x12, 0x00], Quirks::default(), HeadlessIo::new(0)).unwrap();
+        assert_eq!(other.load_state(&saved), Err(StateError::WrongRom));
+        assert_eq!(
+            chip8.load_state(&other.save_state()),
+            Err(StateError::WrongRom)
+        );
+
+        let mut xo_chip = machine(Quirks::preset("xochip").unwrap());
+        assert_eq!(
+            chip8.load_state(&xo_chip.save_state()),
+            Err(StateError::Invalid("memory is the wrong size"))
+        );
+        assert_eq!(
+            xo_chip.load_state(&saved),
+            Err(StateError::Invalid("memory is the wrong size"))
+        );
+
+        // None of that changed anything, including the backend.
+        assert_eq!(chip8.save_state(), before);
+    }
+}
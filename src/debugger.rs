@@ -0,0 +1,1010 @@
+//! Breakpoints and stepping, independent of any particular user interface.
+//!
+//! A front end shows `Debugger::panel` while paused, turns what the user
+//! types into a `Command` with `Command::parse`, and hands it to
+//! `Debugger::command`. While running, it passes `Debugger::should_stop` to
+//! `Chip8::run_frame_until`.
+//!
+//! Breakpoints stop before an instruction runs. Watchpoints are checked
+//! after each instruction, by comparing against the state before it, so they
+//! can say exactly which instruction touched what.
+//!
+//! It also keeps the cheat search and the list of frozen addresses (see
+//! `cheat`), but leaves applying the cheats, and saving them, to the front
+//! end.
+
+use crate::cheat::{Cheat, CheatList, Filter, Search};
+use crate::cpu::{
+    error::Chip8Error, instruction::Instruction, io::Chip8Io, mem::MemAccess, regs::Regs, Chip8,
+    Step,
+};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display},
+};
+
+/// How many rows of memory the panel shows.
+const HEXDUMP_ROWS: usize = 8;
+
+/// How many lines from logging watchpoints the panel shows.
+const LOG_LINES: usize = 8;
+
+/// How many cheat search candidates the panel shows.
+const CANDIDATES_SHOWN: usize = 6;
+
+/// An opcode with some nibbles left as wildcards, e.g. `Dxyn` or `2nnn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    value: u16,
+    /// Bits that have to match `value`.
+    mask: u16,
+}
+
+impl OpcodePattern {
+    /// Four characters, each a hex digit or a wildcard: `x`, `y`, `n`, `k`,
+    /// `?`, or `.`.
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.chars().count() != 4 {
+            return None;
+        }
+
+        let mut value = 0;
+        let mut mask = 0;
+        for c in s.chars() {
+            value <<= 4;
+            mask <<= 4;
+            match c {
+                'x' | 'y' | 'n' | 'k' | 'X' | 'Y' | 'N' | 'K' | '?' | '.' => {}
+                _ => {
+                    value |= c.to_digit(16)? as u16;
+                    mask |= 0xf;
+                }
+            }
+        }
+        Some(Self { value, mask })
+    }
+
+    pub fn matches(self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for shift in [12, 8, 4, 0] {
+            if self.mask >> shift & 0xf == 0 {
+                write!(f, "?")?;
+            } else {
+                write!(f, "{:X}", self.value >> shift & 0xf)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stop before running the instruction at this address.
+    Address(u16),
+    /// Stop before running any instruction that matches.
+    Opcode(OpcodePattern),
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Address(addr) => write!(f, "at 0x{addr:03x}"),
+            Breakpoint::Opcode(pattern) => write!(f, "on {pattern}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl Register {
+    fn get(self, v: &Regs, i: u16) -> u16 {
+        match self {
+            Register::V(x) => v[x] as u16,
+            Register::I => i,
+        }
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "v{x:x}"),
+            Register::I => write!(f, "i"),
+        }
+    }
+}
+
+/// Which changes to a register a watchpoint cares about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Any,
+    To(u16),
+    From(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// Data reads and/or writes of any byte in `start..=end`: `Fx33` and
+    /// `Fx55` write, and `Fx65`, `Dxyn`, and `F002` read.
+    Memory {
+        start: u16,
+        end: u16,
+        read: bool,
+        write: bool,
+    },
+    Register(Register, Change),
+}
+
+impl Watchpoint {
+    /// If the last instruction, which went from `before` to `after`, set
+    /// this off, describe what it did.
+    fn check(self, before: &Snapshot, after: &Snapshot, accesses: &[MemAccess]) -> Option<String> {
+        match self {
+            Watchpoint::Memory {
+                start,
+                end,
+                read,
+                write,
+            } => {
+                let access = accesses.iter().find(|a| {
+                    (if a.write { write } else { read }) && a.overlaps(start as usize, end as usize)
+                })?;
+                let verb = if access.write { "wrote" } else { "read" };
+                let mut what = format!("{verb} 0x{:03x}", access.start);
+                if access.len > 1 {
+                    what += &format!("-0x{:03x}", access.start + access.len - 1);
+                }
+                Some(what)
+            }
+            Watchpoint::Register(reg, change) => {
+                let old = reg.get(&before.v, before.i);
+                let new = reg.get(&after.v, after.i);
+                let hit = old != new
+                    && match change {
+                        Change::Any => true,
+                        Change::To(n) => new == n,
+                        Change::From(n) => old == n,
+                    };
+                hit.then(|| format!("{reg} 0x{old:02x} -> 0x{new:02x}"))
+            }
+        }
+    }
+}
+
+impl Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Watchpoint::Memory {
+                start,
+                end,
+                read,
+                write,
+            } => {
+                let access = match (read, write) {
+                    (true, false) => "reads of",
+                    (false, true) => "writes to",
+                    _ => "reads and writes of",
+                };
+                write!(f, "{access} 0x{start:03x}")?;
+                if end != start {
+                    write!(f, "-0x{end:03x}")?;
+                }
+                Ok(())
+            }
+            Watchpoint::Register(reg, Change::Any) => write!(f, "{reg} changing"),
+            Watchpoint::Register(reg, Change::To(n)) => write!(f, "{reg} changing to 0x{n:02x}"),
+            Watchpoint::Register(reg, Change::From(n)) => {
+                write!(f, "{reg} changing from 0x{n:02x}")
+            }
+        }
+    }
+}
+
+/// What to do when a watchpoint goes off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Break,
+    /// Add a line to the panel's log, and keep going.
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Run this many instructions.
+    Step(u32),
+    /// Like `Step(1)`, but run a whole subroutine if it's a `2nnn` call.
+    Next,
+    Continue,
+    /// Continue until `pc` reaches this address.
+    Until(u16),
+    Break(Breakpoint),
+    /// Remove the breakpoint with this index.
+    Delete(usize),
+    Watch(Watchpoint, Action),
+    /// Remove the watchpoint with this index.
+    DeleteWatch(usize),
+    /// Show memory starting here, instead of at `i`.
+    Memory(Option<u16>),
+    /// Start a cheat search with `None`, or narrow it down.
+    Search(Option<Filter>),
+    /// Hold an address at a value.
+    Freeze(u16, u8),
+    Unfreeze(u16),
+    Quit,
+}
+
+/// A short reference for the `Command` syntax.
+pub const HELP: &str = "\
+s [N]                  step N instructions
+n                      step over 2nnn calls
+c                      continue
+u ADDR                 run until ADDR
+b ADDR                 break at ADDR
+bo OPCODE              break on OPCODE, e.g. Dxyn
+d N                    delete breakpoint N
+w ADDR[-END] [r|w|rw]  watch memory, reads and writes by default
+w vX|i [to N|from N]   watch a register change
+l ...                  like w, but log instead of breaking
+dw N                   delete watchpoint N
+m [ADDR]               show memory at ADDR, or at i
+cs                     start a cheat search
+cs changed|unchanged|N narrow it to changed, unchanged, or N
+f ADDR N               freeze ADDR at N
+uf ADDR                unfreeze ADDR
+q                      quit";
+
+impl Command {
+    /// Addresses and values are in hex, with or without `0x`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let command = match words[..] {
+            ["s" | "step"] => Command::Step(1),
+            ["s" | "step", n] => Command::Step(n.parse().map_err(|_| format!("bad count {n:?}"))?),
+            ["n" | "next"] => Command::Next,
+            ["c" | "continue"] => Command::Continue,
+            ["u" | "until", addr] => Command::Until(parse_addr(addr)?),
+            ["b" | "break", addr] => Command::Break(Breakpoint::Address(parse_addr(addr)?)),
+            ["bo", pattern] => Command::Break(Breakpoint::Opcode(
+                OpcodePattern::parse(pattern).ok_or(format!("bad opcode pattern {pattern:?}"))?,
+            )),
+            ["d" | "delete", n] => {
+                Command::Delete(n.parse().map_err(|_| format!("bad index {n:?}"))?)
+            }
+            ["w" | "watch", ref spec @ ..] => {
+                Command::Watch(parse_watchpoint(spec)?, Action::Break)
+            }
+            ["l" | "log", ref spec @ ..] => Command::Watch(parse_watchpoint(spec)?, Action::Log),
+            ["dw", n] => Command::DeleteWatch(n.parse().map_err(|_| format!("bad index {n:?}"))?),
+            ["m" | "mem"] => Command::Memory(None),
+            ["m" | "mem", addr] => Command::Memory(Some(parse_addr(addr)?)),
+            ["cs" | "search"] => Command::Search(None),
+            ["cs" | "search", "changed"] => Command::Search(Some(Filter::Changed)),
+            ["cs" | "search", "unchanged"] => Command::Search(Some(Filter::Unchanged)),
+            ["cs" | "search", n] => Command::Search(Some(Filter::Equals(parse_byte(n)?))),
+            ["f" | "freeze", addr, n] => Command::Freeze(parse_addr(addr)?, parse_byte(n)?),
+            ["uf" | "unfreeze", addr] => Command::Unfreeze(parse_addr(addr)?),
+            ["q" | "quit"] => Command::Quit,
+            _ => return Err(format!("unknown command {s:?}")),
+        };
+        Ok(command)
+    }
+}
+
+fn parse_addr(s: &str) -> Result<u16, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad address {s:?}"))
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u8::from_str_radix(digits, 16).map_err(|_| format!("bad value {s:?}"))
+}
+
+/// `ADDR[-END] [r|w|rw]`, `vX [to N|from N]`, or `i [to N|from N]`.
+fn parse_watchpoint(words: &[&str]) -> Result<Watchpoint, String> {
+    let (&first, rest) = words.split_first().ok_or("watch what?")?;
+
+    let reg = match first {
+        "i" => Some(Register::I),
+        _ => first
+            .strip_prefix('v')
+            .and_then(|x| u8::from_str_radix(x, 16).ok())
+            .filter(|&x| x <= 0xf)
+            .map(Register::V),
+    };
+    if let Some(reg) = reg {
+        let change = match rest {
+            [] => Change::Any,
+            ["to", n] => Change::To(parse_value(reg, n)?),
+            ["from", n] => Change::From(parse_value(reg, n)?),
+            _ => return Err(format!("expected `to N` or `from N` after {first}")),
+        };
+        return Ok(Watchpoint::Register(reg, change));
+    }
+
+    let (start, end) = match first.split_once('-') {
+        Some((start, end)) => (parse_addr(start)?, parse_addr(end)?),
+        None => (parse_addr(first)?, parse_addr(first)?),
+    };
+    if end < start {
+        return Err(format!("bad range {first:?}"));
+    }
+    let (read, write) = match rest {
+        [] | ["rw"] => (true, true),
+        ["r"] => (true, false),
+        ["w"] => (false, true),
+        _ => return Err("expected r, w, or rw after the address".to_string()),
+    };
+    Ok(Watchpoint::Memory {
+        start,
+        end,
+        read,
+        write,
+    })
+}
+
+fn parse_value(reg: Register, s: &str) -> Result<u16, String> {
+    let n = parse_addr(s).map_err(|_| format!("bad value {s:?}"))?;
+    if matches!(reg, Register::V(_)) && n > 0xff {
+        return Err(format!("{reg} can't be {s}"));
+    }
+    Ok(n)
+}
+
+/// What to do after a `Command`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Stay paused, and ask for another command.
+    Paused,
+    /// Go back to running frames.
+    Running,
+    Quit,
+}
+
+/// For a `stop` callback that checks breakpoints: don't stop before the
+/// first instruction after resuming, or we'd never get past a breakpoint.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ResumeGuard {
+    resuming: bool,
+}
+
+impl ResumeGuard {
+    pub(crate) fn resume(&mut self) {
+        self.resuming = true;
+    }
+
+    /// Is this the first check since `resume`? Only true once.
+    pub(crate) fn just_resumed(&mut self) -> bool {
+        std::mem::take(&mut self.resuming)
+    }
+}
+
+/// What watchpoints compare against.
+#[derive(Debug, Clone)]
+struct Snapshot {
+    cycles: u64,
+    pc: u16,
+    /// What's at `pc`, before self-modifying code can change it.
+    instr: Option<Instruction>,
+    i: u16,
+    v: Regs,
+}
+
+impl Snapshot {
+    fn of<IO: Chip8Io>(chip8: &Chip8<IO>) -> Self {
+        Self {
+            cycles: chip8.cycles(),
+            pc: chip8.pc(),
+            instr: chip8.instruction_at(chip8.pc()),
+            i: chip8.i(),
+            v: chip8.v().clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<(Watchpoint, Action)>,
+    /// The state the last time watchpoints were checked.
+    last: Option<Snapshot>,
+    /// Output from logging watchpoints, oldest first.
+    log: VecDeque<String>,
+    /// A temporary stop from `Next` or `Until`: the address, and the stack
+    /// depth it has to be reached at, if any.
+    target: Option<(u16, Option<usize>)>,
+    guard: ResumeGuard,
+    /// Where the hexdump starts, or `None` to follow `i`.
+    memory: Option<u16>,
+    /// Why we last paused, or the result of the last command.
+    message: String,
+    search: Option<Search>,
+    cheats: CheatList,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[(Watchpoint, Action)] {
+        &self.watchpoints
+    }
+
+    /// The front end should apply these once per frame, and save them after
+    /// a `Freeze` or `Unfreeze`.
+    pub fn cheats(&self) -> &CheatList {
+        &self.cheats
+    }
+
+    /// E.g. the ones saved for this ROM.
+    pub fn set_cheats(&mut self, cheats: CheatList) {
+        self.cheats = cheats;
+    }
+
+    /// For `Chip8::run_frame_until`: should execution pause before the next
+    /// instruction?
+    pub fn should_stop<IO: Chip8Io>(&mut self, chip8: &Chip8<IO>) -> bool {
+        if self.check_watchpoints(chip8) {
+            return true;
+        }
+        if self.guard.just_resumed() {
+            return false;
+        }
+
+        let pc = chip8.pc();
+        if let Some((addr, depth)) = self.target {
+            if pc == addr && depth.is_none_or(|d| chip8.stack().len() == d) {
+                self.target = None;
+                self.message = format!("reached 0x{addr:03x}");
+                return true;
+            }
+        }
+
+        let opcode = chip8
+            .memory()
+            .get(pc as usize..pc as usize + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]));
+        for (n, bp) in self.breakpoints.iter().enumerate() {
+            let hit = match *bp {
+                Breakpoint::Address(addr) => pc == addr,
+                Breakpoint::Opcode(pattern) => opcode.is_some_and(|op| pattern.matches(op)),
+            };
+            if hit {
+                self.message = format!("breakpoint {n} {bp}");
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Compare against the state from the last check, which should be from
+    /// right before the last instruction. Returns whether to break.
+    fn check_watchpoints<IO: Chip8Io>(&mut self, chip8: &Chip8<IO>) -> bool {
+        let after = Snapshot::of(chip8);
+        let Some(before) = self.last.replace(after.clone()) else {
+            return false;
+        };
+        // Nothing has run since.
+        if before.cycles == after.cycles {
+            return false;
+        }
+
+        let mut stop = false;
+        for (n, &(watchpoint, action)) in self.watchpoints.iter().enumerate() {
+            let Some(what) = watchpoint.check(&before, &after, chip8.last_accesses()) else {
+                continue;
+            };
+            let instr = match before.instr {
+                Some(instr) => instr.to_string(),
+                None => "???".to_string(),
+            };
+            let line = format!("watch {n}: 0x{:03x} {instr} {what}", before.pc);
+            match action {
+                Action::Break => {
+                    self.message = line;
+                    stop = true;
+                }
+                Action::Log => {
+                    if self.log.len() == LOG_LINES {
+                        self.log.pop_front();
+                    }
+                    self.log.push_back(line);
+                }
+            }
+        }
+        stop
+    }
+
+    /// Pause, e.g. because the user asked to. `why` is shown in the panel.
+    pub fn pause(&mut self, why: &str) {
+        self.target = None;
+        self.message = why.to_string();
+    }
+
+    /// Show something in the panel, e.g. an error from `Command::parse`.
+    pub fn set_message(&mut self, message: String) {
+        self.message = message;
+    }
+
+    /// Run a command while paused.
+    pub fn command<IO: Chip8Io>(
+        &mut self,
+        command: Command,
+        chip8: &mut Chip8<IO>,
+    ) -> Result<Resume, Chip8Error> {
+        self.message.clear();
+        match command {
+            Command::Step(n) => {
+                for _ in 0..n {
+                    if !self.step(chip8)? {
+                        break;
+                    }
+                }
+                chip8.present();
+            }
+            Command::Next => {
+                let pc = chip8.pc();
+                if let Some(Instruction::Call(_)) = chip8.instruction_at(pc) {
+                    self.target = Some((pc.wrapping_add(2), Some(chip8.stack().len())));
+                    return Ok(self.resume());
+                }
+                self.step(chip8)?;
+                chip8.present();
+            }
+            Command::Continue => return Ok(self.resume()),
+            Command::Until(addr) => {
+                self.target = Some((addr, None));
+                return Ok(self.resume());
+            }
+            Command::Break(bp) => {
+                self.message = format!("breakpoint {} {bp}", self.breakpoints.len());
+                self.breakpoints.push(bp);
+            }
+            Command::Delete(n) => {
+                if n < self.breakpoints.len() {
+                    let bp = self.breakpoints.remove(n);
+                    self.message = format!("deleted breakpoint {n} {bp}");
+                } else {
+                    self.message = format!("no breakpoint {n}");
+                }
+            }
+            Command::Watch(watchpoint, action) => {
+                self.message = format!("watchpoint {} {watchpoint}", self.watchpoints.len());
+                self.watchpoints.push((watchpoint, action));
+            }
+            Command::DeleteWatch(n) => {
+                if n < self.watchpoints.len() {
+                    let (watchpoint, _) = self.watchpoints.remove(n);
+                    self.message = format!("deleted watchpoint {n} {watchpoint}");
+                } else {
+                    self.message = format!("no watchpoint {n}");
+                }
+            }
+            Command::Memory(addr) => self.memory = addr,
+            Command::Search(None) => {
+                let search = Search::new(chip8.memory());
+                self.message = format!("searching {} addresses", search.candidates().len());
+                self.search = Some(search);
+            }
+            Command::Search(Some(filter)) => match &mut self.search {
+                Some(search) => {
+                    self.message = if search.narrow(chip8.memory(), filter) {
+                        match search.candidates().len() {
+                            1 => "1 candidate left".to_string(),
+                            n => format!("{n} candidates left"),
+                        }
+                    } else {
+                        "memory changed size; start again with cs".to_string()
+                    };
+                }
+                None => self.message = "no search yet; start one with cs".to_string(),
+            },
+            Command::Freeze(addr, value) => {
+                if addr as usize >= chip8.memory().len() {
+                    self.message = format!("no address 0x{addr:03x}");
+                } else {
+                    self.cheats.freeze(Cheat {
+                        addr,
+                        value,
+                        note: String::new(),
+                    });
+                    self.message = format!("froze 0x{addr:03x} at 0x{value:02x}");
+                }
+            }
+            Command::Unfreeze(addr) => {
+                self.message = if self.cheats.unfreeze(addr) {
+                    format!("unfroze 0x{addr:03x}")
+                } else {
+                    format!("0x{addr:03x} isn't frozen")
+                };
+            }
+            Command::Quit => return Ok(Resume::Quit),
+        }
+        Ok(Resume::Paused)
+    }
+
+    fn resume(&mut self) -> Resume {
+        self.guard.resume();
+        Resume::Running
+    }
+
+    /// Step one instruction, and return whether to keep stepping.
+    fn step<IO: Chip8Io>(&mut self, chip8: &mut Chip8<IO>) -> Result<bool, Chip8Error> {
+        // Catch up on the last instruction before we paused.
+        if self.check_watchpoints(chip8) {
+            return Ok(false);
+        }
+
+        if chip8.single_step()? == Step::Halted {
+            self.message = "halted".to_string();
+            return Ok(false);
+        }
+        Ok(!self.check_watchpoints(chip8))
+    }
+
+    /// Lines of text describing the machine's state, to show while paused.
+    pub fn panel<IO: Chip8Io>(&self, chip8: &Chip8<IO>) -> Vec<String> {
+        let pc = chip8.pc();
+        let instr = match chip8.instruction_at(pc) {
+            Some(instr) => instr.to_string(),
+            None => "???".to_string(),
+        };
+
+        let mut lines = vec![
+            format!("pc     0x{pc:03x}  {instr}"),
+            format!("i      0x{:03x}", chip8.i()),
+            format!("v      {:?}", chip8.v()),
+            format!(
+                "dt {:3}  st {:3}  cycle {}",
+                chip8.delay_timer(),
+                chip8.sound_timer(),
+                chip8.cycles()
+            ),
+        ];
+
+        let stack: Vec<String> = chip8.stack().iter().map(|a| format!("0x{a:03x}")).collect();
+        lines.push(format!("stack  [{}]", stack.join(", ")));
+
+        lines.push(String::new());
+        let hexdump = format!(
+            "{:?}",
+            chip8.hexdump(self.memory.unwrap_or(chip8.i()), HEXDUMP_ROWS)
+        );
+        lines.extend(hexdump.lines().map(str::to_string));
+
+        lines.push(String::new());
+        for (n, bp) in self.breakpoints.iter().enumerate() {
+            lines.push(format!("{n}: {bp}"));
+        }
+
+        for (n, (watchpoint, action)) in self.watchpoints.iter().enumerate() {
+            let action = match action {
+                Action::Break => "",
+                Action::Log => " (log)",
+            };
+            lines.push(format!("w{n}: {watchpoint}{action}"));
+        }
+
+        for cheat in self.cheats.as_slice() {
+            let mut line = format!("0x{:03x} frozen at 0x{:02x}", cheat.addr, cheat.value);
+            if !cheat.note.is_empty() {
+                line += &format!("  {}", cheat.note);
+            }
+            lines.push(line);
+        }
+
+        if let Some(search) = &self.search {
+            let candidates = search.candidates();
+            let mut shown: Vec<String> = candidates
+                .iter()
+                .take(CANDIDATES_SHOWN)
+                .map(|&addr| format!("0x{addr:03x}={:02x}", chip8.memory()[addr as usize]))
+                .collect();
+            if candidates.len() > CANDIDATES_SHOWN {
+                shown.push("...".to_string());
+            }
+            lines.push(format!("cheat search: {} left", candidates.len()));
+            if !shown.is_empty() {
+                lines.push(format!("    {}", shown.join(" ")));
+            }
+        }
+
+        if !self.log.is_empty() {
+            lines.push(String::new());
+            lines.extend(self.log.iter().cloned());
+        }
+
+        lines.push(String::new());
+        lines.extend(HELP.lines().map(str::to_string));
+
+        if !self.message.is_empty() {
+            lines.push(String::new());
+            lines.push(self.message.clone());
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, HeadlessIo, Quirks};
+
+    fn machine(source: &str) -> Chip8<HeadlessIo> {
+        let rom = asm::assemble(source).unwrap();
+        Chip8::new(&rom, Quirks::default(), HeadlessIo::new(0)).unwrap()
+    }
+
+    /// Recurses once, so `sub` runs at stack depths 1, 2, and then 0.
+    const RECURSE: &str = "
+                CALL sub
+        sub:    ADD V0, 1
+                SE V0, 2
+                CALL sub
+                RET
+    ";
+
+    #[test]
+    fn parse() {
+        let pattern = |s| OpcodePattern::parse(s).unwrap();
+        for (s, command) in [
+            ("s", Command::Step(1)),
+            ("step 10", Command::Step(10)),
+            ("n", Command::Next),
+            ("  c  ", Command::Continue),
+            ("u 0x2a0", Command::Until(0x2a0)),
+            ("b 2a0", Command::Break(Breakpoint::Address(0x2a0))),
+            (
+                "bo Dxyn",
+                Command::Break(Breakpoint::Opcode(pattern("Dxyn"))),
+            ),
+            ("d 1", Command::Delete(1)),
+            (
+                "w 300-30f r",
+                Command::Watch(
+                    Watchpoint::Memory {
+                        start: 0x300,
+                        end: 0x30f,
+                        read: true,
+                        write: false,
+                    },
+                    Action::Break,
+                ),
+            ),
+            (
+                "l vf from 1",
+                Command::Watch(
+                    Watchpoint::Register(Register::V(0xf), Change::From(1)),
+                    Action::Log,
+                ),
+            ),
+            (
+                "w i to 0x400",
+                Command::Watch(
+                    Watchpoint::Register(Register::I, Change::To(0x400)),
+                    Action::Break,
+                ),
+            ),
+            ("dw 0", Command::DeleteWatch(0)),
+            ("m", Command::Memory(None)),
+            ("m 300", Command::Memory(Some(0x300))),
+            ("cs", Command::Search(None)),
+            ("cs changed", Command::Search(Some(Filter::Changed))),
+            ("cs 0x0a", Command::Search(Some(Filter::Equals(0x0a)))),
+            ("f 2f0 9", Command::Freeze(0x2f0, 9)),
+            ("uf 2f0", Command::Unfreeze(0x2f0)),
+            ("q", Command::Quit),
+        ] {
+            assert_eq!(Command::parse(s), Ok(command), "{s:?}");
+        }
+
+        for s in [
+            "",
+            "jump",
+            "s -1",
+            "s 1 2",
+            "b",
+            "b 10000",
+            "b xyz",
+            "bo Dxy",
+            "bo Gxyn",
+            "d one",
+            "w",
+            "w 30f-300",
+            "w 300 x",
+            "w v0 to 100",
+            "w v0 by 1",
+            "cs 100",
+            "f 2f0",
+        ] {
+            assert!(Command::parse(s).is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn opcode_pattern() {
+        let pattern = OpcodePattern::parse("Dxyn").unwrap();
+        assert!(pattern.matches(0xd123));
+        assert!(pattern.matches(0xdfff));
+        assert!(!pattern.matches(0xe123));
+        assert_eq!(pattern.to_string(), "D???");
+
+        let pattern = OpcodePattern::parse("8..e").unwrap();
+        assert!(pattern.matches(0x812e));
+        assert!(!pattern.matches(0x8126));
+
+        let exact = OpcodePattern::parse("00e0").unwrap();
+        assert!(exact.matches(0x00e0));
+        assert!(!exact.matches(0x00ee));
+
+        for s in ["", "Dxy", "Dxyn0", "Dxyz", "D\u{e9}yn"] {
+            assert_eq!(OpcodePattern::parse(s), None, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn next_over_recursion() {
+        let mut chip8 = machine(RECURSE);
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            debugger.command(Command::Next, &mut chip8),
+            Ok(Resume::Running)
+        );
+
+        // 0x202 comes up twice inside the call before it returns there.
+        let step = chip8
+            .run_frame_until(|chip8| debugger.should_stop(chip8))
+            .unwrap();
+        assert_eq!(step, Step::Stopped);
+        assert_eq!(chip8.pc(), 0x202);
+        assert!(chip8.stack().is_empty());
+        assert_eq!(chip8.v()[0], 2);
+        assert_eq!(debugger.message, "reached 0x202");
+
+        // Not a call, so it's just a step.
+        assert_eq!(
+            debugger.command(Command::Next, &mut chip8),
+            Ok(Resume::Paused)
+        );
+        assert_eq!(chip8.pc(), 0x204);
+    }
+
+    #[test]
+    fn next_over_return() {
+        let mut chip8 = machine(RECURSE);
+        let mut debugger = Debugger::new();
+        debugger.command(Command::Step(6), &mut chip8).unwrap();
+        assert_eq!(chip8.pc(), 0x208);
+        assert_eq!(chip8.stack().len(), 2);
+
+        assert_eq!(
+            debugger.command(Command::Next, &mut chip8),
+            Ok(Resume::Paused)
+        );
+        assert_eq!(chip8.pc(), 0x208);
+        assert_eq!(chip8.stack().len(), 1);
+    }
+
+    #[test]
+    fn until_any_depth() {
+        let mut chip8 = machine(RECURSE);
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            debugger.command(Command::Until(0x202), &mut chip8),
+            Ok(Resume::Running)
+        );
+
+        let step = chip8
+            .run_frame_until(|chip8| debugger.should_stop(chip8))
+            .unwrap();
+        assert_eq!(step, Step::Stopped);
+        assert_eq!(chip8.pc(), 0x202);
+        assert_eq!(chip8.stack().len(), 1);
+    }
+
+    #[test]
+    fn register_watchpoint() {
+        let mut chip8 = machine(RECURSE);
+        let mut debugger = Debugger::new();
+        debugger
+            .command(Command::parse("w v0 to 2").unwrap(), &mut chip8)
+            .unwrap();
+
+        let step = chip8
+            .run_frame_until(|chip8| debugger.should_stop(chip8))
+            .unwrap();
+        assert_eq!(step, Step::Stopped);
+        assert_eq!(chip8.v()[0], 2);
+        assert_eq!(chip8.stack().len(), 2);
+        assert_eq!(
+            debugger.message,
+            "watch 0: 0x202 ADD V0, 0x01 v0 0x01 -> 0x02"
+        );
+    }
+
+    #[test]
+    fn memory_watchpoint() {
+        let mut chip8 = machine(
+            "
+                    LD I, 0x300
+                    LD [I], V1
+                    LD I, 0x2fe
+                    LD [I], V1
+                    LD I, 0x2ff
+                    LD V1, [I]
+            end:    JP end
+            ",
+        );
+        let mut debugger = Debugger::new();
+        debugger
+            .command(Command::parse("w 300-301 r").unwrap(), &mut chip8)
+            .unwrap();
+
+        // Skips the writes, and the read of 0x2fe that doesn't overlap.
+        let step = chip8
+            .run_frame_until(|chip8| debugger.should_stop(chip8))
+            .unwrap();
+        assert_eq!(step, Step::Stopped);
+        assert_eq!(chip8.pc(), 0x20c);
+        assert_eq!(
+            debugger.message,
+            "watch 0: 0x20a LD V1, [I] read 0x2ff-0x300"
+        );
+    }
+
+    #[test]
+    fn log_only() {
+        let mut chip8 = machine(RECURSE);
+        let mut debugger = Debugger::new();
+        debugger
+            .command(Command::parse("l v0").unwrap(), &mut chip8)
+            .unwrap();
+
+        let step = chip8
+            .run_frame_until(|chip8| debugger.should_stop(chip8))
+            .unwrap();
+        assert_ne!(step, Step::Stopped);
+        // Logging doesn't replace the message.
+        assert_eq!(debugger.message, "watchpoint 0 v0 changing");
+        assert_eq!(debugger.log.len(), 3);
+        assert_eq!(
+            debugger.log[0],
+            "watch 0: 0x202 ADD V0, 0x01 v0 0x00 -> 0x01"
+        );
+        assert_eq!(
+            debugger.log[2],
+            "watch 0: 0x202 ADD V0, 0x01 v0 0x02 -> 0x03"
+        );
+    }
+
+    #[test]
+    fn watchpoint_before_halt() {
+        let rom = asm::assemble("LD I, 0x300\nLD [I], V0\nend: JP end").unwrap();
+        let mut chip8 = Chip8::new(&rom, Quirks::default(), HeadlessIo::new(0)).unwrap();
+        let mut debugger = Debugger::new();
+        debugger
+            .command(Command::parse("w 300").unwrap(), &mut chip8)
+            .unwrap();
+
+        let step = chip8
+            .run_frame_until(|chip8| debugger.should_stop(chip8))
+            .unwrap();
+        assert_eq!(step, Step::Stopped);
+        assert_eq!(debugger.message, "watch 0: 0x202 LD [I], V0 wrote 0x300");
+    }
+}
@@ -0,0 +1,85 @@
+//! Turn ROMs back into (approximately) readable instructions.
+
+use crate::cpu::{
+    error::Chip8Error,
+    instruction::{Instruction, LONG_PREFIX},
+    mem::Mem,
+};
+use std::fmt::{self, Display};
+
+/// One line of a disassembly listing: either an instruction, or bytes that
+/// don't decode as one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    /// `None` for data bytes.
+    pub instr: Option<Instruction>,
+}
+
+/// Decode every word of the ROM, in order, as if it were loaded at
+/// `Mem::ROM_START`.
+///
+/// There's no attempt to follow control flow, so sprite data that happens to
+/// look like an instruction will be shown as one.
+///
+/// Fails if the ROM wouldn't fit in even the largest (XO-CHIP) memory.
+pub fn disassemble(rom: &[u8]) -> Result<Vec<Line>, Chip8Error> {
+    let max = Mem::XO_CHIP_LEN - Mem::ROM_START as usize;
+    if rom.len() > max {
+        return Err(Chip8Error::RomTooLarge {
+            len: rom.len(),
+            max,
+        });
+    }
+
+    let mut lines = vec![];
+
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = Mem::ROM_START + offset as u16;
+        let rest = &rom[offset..];
+
+        let instr = decode_prefix(rest);
+        let len = match instr {
+            Some(instr) => instr.size() as usize,
+            None => rest.len().min(2),
+        };
+
+        lines.push(Line {
+            addr,
+            bytes: rest[..len].to_vec(),
+            instr,
+        });
+        offset += len;
+    }
+
+    Ok(lines)
+}
+
+/// Decode the instruction at the start of `bytes`, if any.
+pub(crate) fn decode_prefix(bytes: &[u8]) -> Option<Instruction> {
+    let word = |i: usize| Some(u16::from_be_bytes([*bytes.get(i)?, *bytes.get(i + 1)?]));
+
+    let opcode = word(0)?;
+    if opcode == LONG_PREFIX {
+        Some(Instruction::LoadILong(word(2)?))
+    } else {
+        Instruction::decode(opcode)
+    }
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: Vec<_> = self.bytes.iter().map(|b| format!("{b:02x}")).collect();
+        write!(f, "0x{:03x}  {:<11}  ", self.addr, hex.join(" "))?;
+
+        match self.instr {
+            Some(instr) => write!(f, "{instr}"),
+            None => {
+                let data: Vec<_> = self.bytes.iter().map(|b| format!("0x{b:02x}")).collect();
+                write!(f, ":byte {}", data.join(", "))
+            }
+        }
+    }
+}
@@ -0,0 +1,624 @@
+//! A stub for GDB's remote serial protocol, so `gdb` or `lldb` can debug a
+//! running ROM over TCP.
+//!
+//! The registers are `v0` through `vf` (8 bits each), `i` and `pc` (16 bits,
+//! little endian on the wire), and `sp`, the stack depth (8 bits, read
+//! only). They're described to the client in `target.xml`, since neither
+//! debugger knows about CHIP-8. Memory reads and writes go straight to
+//! `Chip8::memory`, and software breakpoints are kept here rather than
+//! patched into memory.
+//!
+//! To connect:
+//!
+//! ```text
+//! (gdb) target remote localhost:PORT
+//! ```
+
+use crate::cpu::{error::Chip8Error, io::Chip8Io, Chip8, Step};
+use crate::debugger::ResumeGuard;
+use std::{
+    collections::{BTreeSet, VecDeque},
+    error::Error,
+    fmt::{self, Display, Write as _},
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+/// The `SIGTRAP` signal number, for stopping at a breakpoint or after a step.
+const SIGTRAP: u8 = 5;
+/// The `SIGINT` signal number, for stopping because the client asked to.
+const SIGINT: u8 = 2;
+
+/// `\x03`, sent outside of a packet to interrupt the target.
+const INTERRUPT: u8 = 0x03;
+
+/// The register numbers of `i`, `pc`, and `sp`. The `V` registers are 0
+/// through 15.
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+
+/// What to do after `GdbStub::serve`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Run until a breakpoint, or until `GdbStub::interrupted`.
+    Continue,
+    /// The client went away. Keep running without it.
+    Detach,
+    /// The client killed the target, or it halted while stepping.
+    Quit,
+}
+
+/// Why the target stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint,
+    Interrupted,
+    /// The program halted. This ends the session.
+    Halted,
+}
+
+#[derive(Debug)]
+pub struct GdbStub {
+    stream: TcpStream,
+    /// Bytes read from `stream` but not parsed yet.
+    buf: VecDeque<u8>,
+    breakpoints: BTreeSet<u16>,
+    guard: ResumeGuard,
+}
+
+impl GdbStub {
+    /// Wait for a client to connect to `addr`.
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Ok(Self::new(stream))
+    }
+
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buf: VecDeque::new(),
+            breakpoints: BTreeSet::new(),
+            guard: ResumeGuard::default(),
+        }
+    }
+
+    /// For `Chip8::run_frame_until`: is there a breakpoint at `pc`?
+    pub fn should_stop<IO: Chip8Io>(&mut self, chip8: &Chip8<IO>) -> bool {
+        if self.guard.just_resumed() {
+            return false;
+        }
+        self.breakpoints.contains(&chip8.pc())
+    }
+
+    /// While running, check whether the client asked to stop, without
+    /// blocking.
+    pub fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let filled = self.fill_buf();
+        self.stream.set_nonblocking(false)?;
+        match filled {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+
+        // Nothing else should arrive while the target is running.
+        let interrupted = self.buf.contains(&INTERRUPT);
+        self.buf.clear();
+        Ok(interrupted)
+    }
+
+    /// Tell the client that the target stopped running.
+    pub fn report_stop(&mut self, stop: Stop) -> io::Result<()> {
+        match stop {
+            Stop::Breakpoint => self.send(&format!("S{SIGTRAP:02x}")),
+            Stop::Interrupted => self.send(&format!("S{SIGINT:02x}")),
+            Stop::Halted => self.send("W00"),
+        }
+    }
+
+    /// Answer requests while the target is stopped, until the client
+    /// resumes it. Single steps are handled here.
+    pub fn serve<IO: Chip8Io>(&mut self, chip8: &mut Chip8<IO>) -> Result<Resume, GdbError> {
+        loop {
+            let Some(packet) = self.read_packet()? else {
+                return Ok(Resume::Detach);
+            };
+
+            let (command, args) = packet.split_at_checked(1).unwrap_or_default();
+            let reply = match command {
+                "?" => format!("S{SIGTRAP:02x}"),
+                "g" => read_registers(chip8),
+                "G" => write_registers(chip8, args),
+                "p" => read_register(chip8, args),
+                "P" => write_register(chip8, args),
+                "m" => read_memory(chip8, args),
+                "M" => write_memory(chip8, args),
+                "Z" | "z" => self.set_breakpoint(command == "Z", args),
+                // An address to resume from that doesn't fit in `pc`.
+                "c" | "s" if !args.is_empty() && parse_addr(args).is_none() => "E01".to_string(),
+                "c" => {
+                    if let Some(addr) = parse_addr(args) {
+                        chip8.set_pc(addr);
+                    }
+                    self.guard.resume();
+                    return Ok(Resume::Continue);
+                }
+                "s" => {
+                    if let Some(addr) = parse_addr(args) {
+                        chip8.set_pc(addr);
+                    }
+                    if chip8.single_step()? == Step::Halted {
+                        self.report_stop(Stop::Halted)?;
+                        return Ok(Resume::Quit);
+                    }
+                    chip8.present();
+                    format!("S{SIGTRAP:02x}")
+                }
+                "D" => {
+                    self.send("OK")?;
+                    return Ok(Resume::Detach);
+                }
+                "k" => return Ok(Resume::Quit),
+                "H" | "T" => "OK".to_string(),
+                _ => query(&packet),
+            };
+            self.send(&reply)?;
+        }
+    }
+
+    /// `Z0,ADDR,KIND` or `z0,ADDR,KIND`. Hardware breakpoints (`Z1`) are
+    /// treated the same as software ones. Watchpoints aren't supported.
+    fn set_breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (Some("0" | "1"), Some(addr)) = (fields.next(), fields.next().and_then(parse_addr))
+        else {
+            return String::new();
+        };
+
+        if insert {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+        "OK".to_string()
+    }
+
+    /// The next packet's contents, acknowledging it. `None` if the client
+    /// disconnected.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks, interrupts that came too late, and anything else
+            // between packets.
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let (Some(hi), Some(lo)) = (self.read_byte()?, self.read_byte()?) else {
+                return Ok(None);
+            };
+
+            let expected = std::str::from_utf8(&[hi, lo])
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+            if expected != Some(checksum(&data)) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.buf.is_empty() && self.fill_buf()? == 0 {
+            return Ok(None);
+        }
+        Ok(self.buf.pop_front())
+    }
+
+    /// Read whatever's available into `buf`. Returns 0 at end of stream.
+    fn fill_buf(&mut self) -> io::Result<usize> {
+        let mut chunk = [0; 1024];
+        let n = self.stream.read(&mut chunk)?;
+        self.buf.extend(&chunk[..n]);
+        Ok(n)
+    }
+
+    /// Send a packet. We don't wait for the ack, or resend if it's `-`,
+    /// since TCP is reliable anyway.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+/// Something went wrong while serving a client.
+#[derive(Debug)]
+pub enum GdbError {
+    Io(io::Error),
+    /// The target faulted while single stepping.
+    Chip8(Chip8Error),
+}
+
+impl Display for GdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "gdb connection: {e}"),
+            Self::Chip8(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for GdbError {}
+
+impl From<io::Error> for GdbError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<Chip8Error> for GdbError {
+    fn from(e: Chip8Error) -> Self {
+        Self::Chip8(e)
+    }
+}
+
+/// Answers for the `q`, `v`, and other packets we care about. An empty reply
+/// means "not supported".
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return "PacketSize=1000;qXfer:features:read+".to_string();
+    }
+    if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        return read_target_xml(args);
+    }
+    match packet {
+        "qAttached" => "1".to_string(),
+        "qfThreadInfo" => "m1".to_string(),
+        "qsThreadInfo" => "l".to_string(),
+        "qC" => "QC1".to_string(),
+        _ => String::new(),
+    }
+}
+
+/// `OFFSET,LENGTH` of the target description.
+fn read_target_xml(args: &str) -> String {
+    let Some((offset, len)) = args.split_once(',') else {
+        return "E01".to_string();
+    };
+    let (Some(offset), Some(len)) = (parse_hex(offset), parse_hex(len)) else {
+        return "E01".to_string();
+    };
+
+    let xml = target_xml();
+    let start = offset.min(xml.len());
+    let end = start.saturating_add(len).min(xml.len());
+    let more = if end < xml.len() { 'm' } else { 'l' };
+    format!("{more}{}", &xml[start..end])
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <feature name=\"org.chip8.core\">",
+    );
+    for x in 0..16 {
+        write!(xml, "<reg name=\"v{x:x}\" bitsize=\"8\" type=\"uint8\"/>").unwrap();
+    }
+    xml += "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\
+            <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+            <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\
+            </feature></target>";
+    xml
+}
+
+fn read_registers<IO: Chip8Io>(chip8: &Chip8<IO>) -> String {
+    (0..=REG_SP).map(|n| register(chip8, n).unwrap()).collect()
+}
+
+/// Every register, in order. A change to `sp` is ignored.
+fn write_registers<IO: Chip8Io>(chip8: &mut Chip8<IO>, args: &str) -> String {
+    let Some(bytes) = decode_hex(args) else {
+        return "E01".to_string();
+    };
+    if bytes.len() < 20 {
+        return "E01".to_string();
+    }
+
+    for x in 0..16 {
+        chip8.v_mut()[x] = bytes[x as usize];
+    }
+    chip8.set_i(u16::from_le_bytes([bytes[16], bytes[17]]));
+    chip8.set_pc(u16::from_le_bytes([bytes[18], bytes[19]]));
+    "OK".to_string()
+}
+
+/// `N`, in hex.
+fn read_register<IO: Chip8Io>(chip8: &Chip8<IO>, args: &str) -> String {
+    parse_hex(args)
+        .and_then(|n| register(chip8, n))
+        .unwrap_or_else(|| "E01".to_string())
+}
+
+/// `N=VALUE`, in hex, with the value in target byte order.
+fn write_register<IO: Chip8Io>(chip8: &mut Chip8<IO>, args: &str) -> String {
+    let Some((n, value)) = args.split_once('=') else {
+        return "E01".to_string();
+    };
+    let (Some(n), Some(value)) = (parse_hex(n), decode_hex(value)) else {
+        return "E01".to_string();
+    };
+
+    match (n, &value[..]) {
+        (0..=15, &[x]) => chip8.v_mut()[n as u8] = x,
+        (REG_I, &[lo, hi]) => chip8.set_i(u16::from_le_bytes([lo, hi])),
+        (REG_PC, &[lo, hi]) => chip8.set_pc(u16::from_le_bytes([lo, hi])),
+        _ => return "E01".to_string(),
+    }
+    "OK".to_string()
+}
+
+/// Register `n` in hex, or `None` if there isn't one.
+fn register<IO: Chip8Io>(chip8: &Chip8<IO>, n: usize) -> Option<String> {
+    let bytes = match n {
+        0..=15 => vec![chip8.v()[n as u8]],
+        REG_I => chip8.i().to_le_bytes().to_vec(),
+        REG_PC => chip8.pc().to_le_bytes().to_vec(),
+        REG_SP => vec![chip8.stack().len() as u8],
+        _ => return None,
+    };
+    Some(encode_hex(&bytes))
+}
+
+/// `ADDR,LENGTH`. Reads past the end of memory are cut short.
+fn read_memory<IO: Chip8Io>(chip8: &Chip8<IO>, args: &str) -> String {
+    let Some((addr, len)) = args.split_once(',') else {
+        return "E01".to_string();
+    };
+    let (Some(addr), Some(len)) = (parse_hex(addr), parse_hex(len)) else {
+        return "E01".to_string();
+    };
+
+    let memory = chip8.memory();
+    if addr >= memory.len() {
+        return "E14".to_string();
+    }
+    let end = addr.saturating_add(len).min(memory.len());
+    encode_hex(&memory[addr..end])
+}
+
+/// `ADDR,LENGTH:BYTES`.
+fn write_memory<IO: Chip8Io>(chip8: &mut Chip8<IO>, args: &str) -> String {
+    let Some((range, data)) = args.split_once(':') else {
+        return "E01".to_string();
+    };
+    let Some((addr, len)) = range.split_once(',') else {
+        return "E01".to_string();
+    };
+    let (Some(addr), Some(len), Some(bytes)) = (parse_hex(addr), parse_hex(len), decode_hex(data))
+    else {
+        return "E01".to_string();
+    };
+    if bytes.len() != len {
+        return "E01".to_string();
+    }
+
+    match chip8.memory_mut().get_mut(addr..addr.saturating_add(len)) {
+        Some(dest) => {
+            dest.copy_from_slice(&bytes);
+            "OK".to_string()
+        }
+        None => "E14".to_string(),
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+/// A CHIP-8 address, which has to fit in 16 bits.
+fn parse_addr(s: &str) -> Option<u16> {
+    u16::try_from(parse_hex(s)?).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, HeadlessIo, Quirks};
+
+    fn machine() -> Chip8<HeadlessIo> {
+        let rom = asm::assemble("LD V0, 1\nLD V1, 2\nend: JP end").unwrap();
+        Chip8::new(&rom, Quirks::default(), HeadlessIo::new(0)).unwrap()
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${data}#{:02x}", checksum(data.as_bytes()))
+    }
+
+    /// A stub, and the client connected to it.
+    fn connect() -> (GdbStub, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (GdbStub::new(stream), client)
+    }
+
+    /// Send `input` from a client, serve it, and return what the client got
+    /// back once the stub hangs up.
+    fn session(chip8: &mut Chip8<HeadlessIo>, input: &str) -> (Resume, String) {
+        let (mut stub, mut client) = connect();
+        client.write_all(input.as_bytes()).unwrap();
+        let resume = stub.serve(chip8).unwrap();
+        drop(stub);
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        (resume, output)
+    }
+
+    #[test]
+    fn framing() {
+        let mut chip8 = machine();
+        let input = format!("+$?#00{}{}", packet("?"), packet("D"));
+        let (resume, output) = session(&mut chip8, &input);
+        assert_eq!(resume, Resume::Detach);
+        // The bad checksum gets a nack, and the rest get acks and replies.
+        assert_eq!(output, format!("-+{}+{}", packet("S05"), packet("OK")));
+    }
+
+    #[test]
+    fn registers() {
+        let mut chip8 = machine();
+        chip8.v_mut()[0] = 0xab;
+        chip8.set_i(0x1234);
+        let v = format!("ab{}", "00".repeat(15));
+        assert_eq!(read_registers(&chip8), format!("{v}3412000200"));
+
+        let v = (0..16).map(|x| format!("{x:02x}")).collect::<String>();
+        assert_eq!(write_registers(&mut chip8, &format!("{v}cdab0403ff")), "OK");
+        assert_eq!(chip8.v()[15], 15);
+        assert_eq!(chip8.i(), 0xabcd);
+        assert_eq!(chip8.pc(), 0x304);
+        assert_eq!(chip8.stack().len(), 0);
+        assert_eq!(write_registers(&mut chip8, "0011"), "E01");
+        assert_eq!(write_registers(&mut chip8, &format!("{v}zzzz0403")), "E01");
+
+        assert_eq!(read_register(&chip8, "f"), "0f");
+        assert_eq!(read_register(&chip8, "10"), "cdab");
+        assert_eq!(read_register(&chip8, "11"), "0403");
+        assert_eq!(read_register(&chip8, "12"), "00");
+        assert_eq!(read_register(&chip8, "13"), "E01");
+
+        assert_eq!(write_register(&mut chip8, "3=7f"), "OK");
+        assert_eq!(chip8.v()[3], 0x7f);
+        assert_eq!(write_register(&mut chip8, "10=0002"), "OK");
+        assert_eq!(chip8.i(), 0x200);
+        assert_eq!(write_register(&mut chip8, "11=0602"), "OK");
+        assert_eq!(chip8.pc(), 0x206);
+        assert_eq!(write_register(&mut chip8, "3=7f7f"), "E01");
+        assert_eq!(write_register(&mut chip8, "12=01"), "E01");
+        assert_eq!(write_register(&mut chip8, "3"), "E01");
+    }
+
+    #[test]
+    fn memory() {
+        let mut chip8 = machine();
+        assert_eq!(read_memory(&chip8, "200,4"), "60016102");
+        assert_eq!(read_memory(&chip8, "ffe,4"), "0000");
+        assert_eq!(read_memory(&chip8, "1000,1"), "E14");
+        assert_eq!(read_memory(&chip8, "200"), "E01");
+
+        assert_eq!(write_memory(&mut chip8, "300,2:beef"), "OK");
+        assert_eq!(read_memory(&chip8, "300,2"), "beef");
+        assert_eq!(write_memory(&mut chip8, "300,3:beef"), "E01");
+        assert_eq!(write_memory(&mut chip8, "fff,2:beef"), "E14");
+        assert_eq!(write_memory(&mut chip8, "300,2"), "E01");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut chip8 = machine();
+        let (mut stub, mut client) = connect();
+        let input = ["Z0,202,2", "Z2,300,1", "Z0,10000,2", "c"]
+            .map(packet)
+            .concat();
+        client.write_all(input.as_bytes()).unwrap();
+        assert_eq!(stub.serve(&mut chip8).unwrap(), Resume::Continue);
+
+        let step = chip8
+            .run_frame_until(|chip8| stub.should_stop(chip8))
+            .unwrap();
+        assert_eq!(step, Step::Stopped);
+        assert_eq!(chip8.pc(), 0x202);
+
+        // With it removed, it runs all the way to the end.
+        let input = ["z0,202,2", "c200"].map(packet).concat();
+        client.write_all(input.as_bytes()).unwrap();
+        assert_eq!(stub.serve(&mut chip8).unwrap(), Resume::Continue);
+        let step = chip8
+            .run_frame_until(|chip8| stub.should_stop(chip8))
+            .unwrap();
+        assert_eq!(step, Step::Halted);
+        drop(stub);
+
+        // Watchpoints and addresses past 0xffff aren't supported.
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        let ok = packet("OK");
+        let unsupported = packet("");
+        assert_eq!(output, format!("+{ok}+{unsupported}+{unsupported}++{ok}+"));
+    }
+
+    #[test]
+    fn resume_address() {
+        let mut chip8 = machine();
+        let input = format!("{}+{}+{}+", packet("s10000"), packet("s202"), packet("D"));
+        let (resume, output) = session(&mut chip8, &input);
+        assert_eq!(resume, Resume::Detach);
+        assert_eq!(
+            output,
+            format!("+{}+{}+{}", packet("E01"), packet("S05"), packet("OK"))
+        );
+        assert_eq!(chip8.pc(), 0x204);
+        assert_eq!(chip8.v()[0], 0);
+        assert_eq!(chip8.v()[1], 2);
+    }
+
+    #[test]
+    fn target_xml_paging() {
+        let xml = target_xml();
+        let mut read = String::new();
+        loop {
+            let reply = query(&format!(
+                "qXfer:features:read:target.xml:{:x},100",
+                read.len()
+            ));
+            let (more, data) = reply.split_at(1);
+            assert!(data.len() <= 0x100);
+            read += data;
+            if more == "l" {
+                break;
+            }
+            assert_eq!(more, "m");
+        }
+        assert_eq!(read, xml);
+
+        let past_end = format!("{:x},10", xml.len() + 5);
+        assert_eq!(read_target_xml(&past_end), "l");
+        assert_eq!(read_target_xml("0"), "E01");
+        assert_eq!(read_target_xml("0,zz"), "E01");
+    }
+}
@@ -0,0 +1,116 @@
+use crate::cpu::io::Chip8Io;
+use crate::cpu::screen::Screen;
+use crate::cpu::state::{StateError, StateReader, StateWriter};
+use crate::movie::{KeyEvent, Movie, ScriptedKeypad};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// A `Chip8Io` that doesn't need a terminal, for tests and tools.
+///
+/// Time is counted in frames: each `update` (which `Chip8::run_frame` calls
+/// once at the end of every frame) advances the clock by one. Key presses
+/// and releases are scripted ahead of time against that clock, and random
+/// numbers come from a seeded generator, so a run is fully reproducible.
+#[derive(Debug, Clone)]
+pub struct HeadlessIo {
+    rng: StdRng,
+    /// The most recently presented screen.
+    screen: Screen,
+    /// How many frames have finished.
+    frame: u64,
+    keypad: ScriptedKeypad,
+}
+
+impl HeadlessIo {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            screen: Screen::new(),
+            frame: 0,
+            keypad: ScriptedKeypad::default(),
+        }
+    }
+
+    /// Replay a recorded session.
+    pub fn from_movie(movie: &Movie) -> Self {
+        let mut this = Self::new(movie.header.seed);
+        for &event in &movie.events {
+            this.keypad.schedule(this.frame, event);
+        }
+        this
+    }
+
+    /// Press `key` at the start of `frame`. Frame 0 is the first one.
+    pub fn press_key(&mut self, frame: u64, key: u8) {
+        let event = KeyEvent {
+            frame,
+            key,
+            pressed: true,
+        };
+        self.keypad.schedule(self.frame, event);
+    }
+
+    /// Release `key` at the start of `frame`.
+    pub fn release_key(&mut self, frame: u64, key: u8) {
+        let event = KeyEvent {
+            frame,
+            key,
+            pressed: false,
+        };
+        self.keypad.schedule(self.frame, event);
+    }
+
+    /// Press `key` at the start of `frame`, and release it `len` frames later.
+    pub fn tap_key(&mut self, frame: u64, key: u8, len: u64) {
+        self.press_key(frame, key);
+        self.release_key(frame + len, key);
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    /// The virtual clock: how many frames have finished.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+impl Chip8Io for HeadlessIo {
+    fn update(&mut self) {
+        self.frame += 1;
+        self.keypad.advance(self.frame);
+    }
+
+    fn present(&mut self, screen: &Screen) {
+        self.screen.clone_from(screen);
+    }
+
+    fn get_random_byte(&mut self) -> u8 {
+        self.rng.gen()
+    }
+
+    fn is_key_pressed(&mut self, k: u8) -> bool {
+        self.keypad.is_key_pressed(k)
+    }
+
+    fn key_released(&mut self) -> Option<u8> {
+        self.keypad.key_released()
+    }
+
+    /// The clock and the keypad are saved, but the RNG and the timeline
+    /// aren't. Loading an earlier state replays the timeline from there.
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.frame);
+        w.u16(self.keypad.pressed_mask());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let frame = r.u64()?;
+        let keys = r.u16()?;
+        r.finish()?;
+
+        self.frame = frame;
+        self.keypad.set_pressed_mask(keys);
+        Ok(())
+    }
+}
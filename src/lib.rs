@@ -1,10 +1,35 @@
 mod cpu;
+mod headless_io;
+mod parse_error;
 mod terminal_io;
 
-use cpu::{io::Chip8Io, Chip8};
+pub mod asm;
+pub mod cheat;
+pub mod coverage;
+pub mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod movie;
+pub mod profile;
+pub mod trace;
 
-pub use terminal_io::TerminalIo;
+pub use cpu::{
+    error::Chip8Error,
+    instruction::{Instruction, LONG_PREFIX},
+    io::{Chip8Io, TIME_BETWEEN_TICKS_NS},
+    mem::MemAccess,
+    quirks::{LoadStoreI, Quirks},
+    regs::Regs,
+    rewind::Rewind,
+    rng::VipRng,
+    screen::{DrawSprite, Point, Screen, Sprite, HIRES_DIMS, LORES_DIMS},
+    state::{StateError, StateReader, StateWriter},
+    Chip8, Step, INSTRUCTIONS_PER_FRAME,
+};
+pub use headless_io::HeadlessIo;
+pub use parse_error::ParseError;
+pub use terminal_io::{Hotkey, TerminalIo};
 
-pub fn run(rom: &[u8], io: &mut dyn Chip8Io) {
-    Chip8::new(rom, io).run()
+pub fn run(rom: &[u8], quirks: Quirks, io: impl Chip8Io) -> Result<(), Chip8Error> {
+    Chip8::new(rom, quirks, io)?.run()
 }
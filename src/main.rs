@@ -1,12 +1,588 @@
-use anyhow::Result;
-use chip_8::TerminalIo;
-use std::io::{self, Read};
+use anyhow::{bail, Context, Result};
+use chip_8::{
+    asm,
+    cheat::{self, CheatList},
+    coverage::Coverage,
+    debugger::{Command, Debugger, Resume},
+    disasm,
+    gdb::{self, GdbStub, Stop},
+    movie::{self, Movie, MovieWriter},
+    profile::Profiler,
+    trace::{self, Divergence, TraceWriter},
+    Chip8, Chip8Io, Hotkey, Quirks, Rewind, Step, TerminalIo, VipRng, INSTRUCTIONS_PER_FRAME,
+};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, ErrorKind, Read, Write},
+    process,
+};
+
+/// How much rewind history to keep, unless `--rewind-budget` says otherwise.
+const DEFAULT_REWIND_BUDGET_MIB: usize = 32;
+
+/// How many instructions `trace-diff` shows leading up to a difference.
+const TRACE_DIFF_CONTEXT: usize = 5;
+
+/// Where F5 saves to and F9 loads from, unless `--state` says otherwise.
+const DEFAULT_STATE_FILE: &str = "chip-8.state";
+
+const USAGE: &str = "\
+usage:
+    chip-8 [OPTIONS] < ROM
+    chip-8 disasm ROM
+    chip-8 asm SOURCE > ROM
+    chip-8 trace-diff TRACE TRACE
+
+options:
+    --quirks PRESET         vip (the default), chip48, schip, or xochip
+    --ipf N                 instructions per 60 Hz frame (default 10)
+    --seed N                make random numbers reproducible
+    --vip-rng               use the COSMAC VIP's random number routine
+    --vip-interpreter FILE  like --vip-rng, reading the code it uses from a
+                            dump of the VIP's CHIP-8 interpreter
+    --state FILE            where F5 saves and F9 loads (default chip-8.state)
+    --rewind-budget MIB     memory for rewinding with Backspace (default 32)
+    --record MOVIE          record keypad input
+    --play MOVIE            replay recorded input, with the same ROM and options
+    --debug                 start paused in the debugger (F8 pauses any time)
+    --gdb PORT              wait for gdb or lldb to connect to localhost:PORT
+    --trace FILE            log the state before every instruction to FILE
+    --profile               count where time goes, and print a report at exit
+    --coverage FILE         write a disassembly marked with what ran at exit
+    --cheats FILE           where the debugger saves frozen addresses
+                            (default chip-8-HASH.cheats, for this ROM)
+
+Rewinding, loading states, cheats, and the debugger are disabled while
+recording or playing a movie.";
 
 fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("disasm") => disasm(&args[1..]),
+        Some("asm") => asm(&args[1..]),
+        Some("trace-diff") => trace_diff(&args[1..]),
+        Some("-h" | "--help") => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => emulate(&args),
+    }
+}
+
+fn emulate(args: &[String]) -> Result<()> {
+    let mut quirks = Quirks::default();
+    let mut quirks_name = "vip".to_string();
+    let mut instructions_per_frame = INSTRUCTIONS_PER_FRAME;
+    let mut state_file = DEFAULT_STATE_FILE.to_string();
+    let mut rewind_budget = DEFAULT_REWIND_BUDGET_MIB << 20;
+    let mut seed = None;
+    let mut vip_rng = false;
+    let mut vip_interpreter = None;
+    let mut record = None;
+    let mut play = None;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut trace_file = None;
+    let mut profile = false;
+    let mut coverage_file = None;
+    let mut cheats_file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().context("--quirks: missing preset name")?;
+                quirks = Quirks::preset(name).with_context(|| {
+                    let names = Quirks::PRESET_NAMES.join(", ");
+                    format!("--quirks: unknown preset {name:?} (expected one of: {names})")
+                })?;
+                quirks_name = name.clone();
+            }
+            "--ipf" => {
+                let n = args
+                    .next()
+                    .context("--ipf: missing instructions per frame")?;
+                instructions_per_frame = n
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .with_context(|| format!("--ipf: expected a positive number, not {n:?}"))?;
+            }
+            "--seed" => {
+                let n = args.next().context("--seed: missing number")?;
+                seed = Some(
+                    n.parse()
+                        .with_context(|| format!("--seed: bad number {n:?}"))?,
+                );
+            }
+            "--vip-rng" => vip_rng = true,
+            "--vip-interpreter" => {
+                let path = args
+                    .next()
+                    .context("--vip-interpreter: missing file name")?;
+                vip_interpreter = Some(path.clone());
+                vip_rng = true;
+            }
+            "--state" => {
+                state_file = args.next().context("--state: missing file name")?.clone();
+            }
+            "--rewind-budget" => {
+                let mib = args.next().context("--rewind-budget: missing size")?;
+                rewind_budget = mib
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|mib| mib.checked_mul(1 << 20))
+                    .with_context(|| format!("--rewind-budget: bad size {mib:?}"))?;
+            }
+            "--record" => {
+                record = Some(args.next().context("--record: missing file name")?.clone());
+            }
+            "--play" => {
+                play = Some(args.next().context("--play: missing file name")?.clone());
+            }
+            "--debug" => debug = true,
+            "--profile" => profile = true,
+            "--coverage" => {
+                coverage_file = Some(
+                    args.next()
+                        .context("--coverage: missing file name")?
+                        .clone(),
+                );
+            }
+            "--cheats" => {
+                cheats_file = Some(args.next().context("--cheats: missing file name")?.clone());
+            }
+            "--trace" => {
+                trace_file = Some(args.next().context("--trace: missing file name")?.clone());
+            }
+            "--gdb" => {
+                let port = args.next().context("--gdb: missing port")?;
+                gdb_port = Some(
+                    port.parse::<u16>()
+                        .with_context(|| format!("--gdb: bad port {port:?}"))?,
+                );
+            }
+            _ => bail!("unexpected argument: {arg:?}\n\n{USAGE}"),
+        }
+    }
+
     let mut rom = vec![];
     io::stdin().read_to_end(&mut rom)?;
 
-    chip_8::run(&rom, &mut TerminalIo::setup()?);
+    if record.is_some() && play.is_some() {
+        bail!("can't --record and --play at the same time");
+    }
+    // Stepping runs instructions outside of the movie's frames.
+    if (debug || gdb_port.is_some()) && (record.is_some() || play.is_some()) {
+        bail!("can't debug while recording or playing a movie");
+    }
+    let movie = match &play {
+        Some(path) => {
+            let bytes = fs::read(path).with_context(|| format!("couldn't read {path:?}"))?;
+            Some(Movie::parse(&bytes).with_context(|| format!("couldn't load {path:?}"))?)
+        }
+        None => None,
+    };
+    let recording = match &record {
+        Some(path) => Some(BufWriter::new(
+            File::create(path).with_context(|| format!("couldn't create {path:?}"))?,
+        )),
+        None => None,
+    };
+    if debug && gdb_port.is_some() {
+        bail!("can't --debug and --gdb at the same time");
+    }
+    let mut gdb = match gdb_port {
+        Some(port) => {
+            eprintln!("waiting for a debugger on localhost:{port}");
+            Some(GdbStub::listen(("127.0.0.1", port)).context("--gdb")?)
+        }
+        None => None,
+    };
+    // The client expects the target to start out stopped.
+    let mut gdb_stopped = gdb.is_some();
+
+    let mut trace = match &trace_file {
+        Some(path) => Some(TraceWriter::new(BufWriter::new(
+            File::create(path).with_context(|| format!("couldn't create {path:?}"))?,
+        ))),
+        None => None,
+    };
+
+    let mut profiler = profile.then(Profiler::new);
+    let mut coverage = coverage_file.as_ref().map(|_| Coverage::new());
+
+    // Going back in time would make the movie out of sync, and so would
+    // changing memory behind its back or stepping in the debugger.
+    let time_travel = movie.is_none() && recording.is_none();
+
+    let cheats_file =
+        cheats_file.unwrap_or_else(|| format!("chip-8-{:016x}.cheats", cheat::rom_id(&rom)));
+    let cheats = match fs::read_to_string(&cheats_file) {
+        Ok(text) => CheatList::parse(&text).unwrap_or_else(|e| {
+            // Don't let a typo in the file keep the game from starting.
+            eprintln!("warning: ignoring {cheats_file:?}: {e}");
+            CheatList::new()
+        }),
+        // No cheats for this ROM yet.
+        Err(e) if e.kind() == ErrorKind::NotFound => CheatList::new(),
+        Err(e) => return Err(e).with_context(|| format!("couldn't read {cheats_file:?}")),
+    };
+
+    let seed = match &movie {
+        Some(movie) => movie.header.seed,
+        None => seed.unwrap_or_else(rand::random),
+    };
+    let header = movie::Header {
+        seed,
+        rom_id: cheat::rom_id(&rom),
+        instructions_per_frame: instructions_per_frame as u64,
+        vip_rng,
+        quirks: quirks_name,
+    };
+    if let (Some(movie), Some(path)) = (&movie, &play) {
+        check_movie(&movie.header, &header).with_context(|| format!("can't play {path:?}"))?;
+    }
+
+    let rng = match &vip_interpreter {
+        _ if !vip_rng => None,
+        Some(path) => {
+            let code = fs::read(path).with_context(|| format!("couldn't read {path:?}"))?;
+            let page = code
+                .get(0x100..0x200)
+                .and_then(|page| page.try_into().ok())
+                .with_context(|| format!("{path:?} is too short for a VIP interpreter"))?;
+            Some(VipRng::with_code_page(seed as u16, page))
+        }
+        None => Some(VipRng::new(seed as u16)),
+    };
+
+    let mut io = TerminalIo::setup()?;
+    io.set_seed(seed);
+    if let Some(movie) = &movie {
+        io.play(movie);
+    }
+    if let Some(out) = recording {
+        io.record(MovieWriter::new(out, &header)?);
+    }
+    let mut chip8 = Chip8::new(&rom, quirks, io)?;
+    chip8.set_instructions_per_frame(instructions_per_frame);
+    chip8.set_vip_rng(rng);
+    let mut rewind = Rewind::new(rewind_budget);
+    let mut debugger = Debugger::new();
+    debugger.set_cheats(cheats);
+    let mut paused = debug;
+    if paused {
+        debugger.pause("paused");
+    }
+    // An empty command repeats the last one.
+    let mut last_command = Command::Step(1);
+    loop {
+        if let Some(stub) = gdb.as_mut().filter(|_| gdb_stopped) {
+            match stub.serve(&mut chip8)? {
+                gdb::Resume::Continue => gdb_stopped = false,
+                gdb::Resume::Detach => {
+                    gdb = None;
+                    gdb_stopped = false;
+                }
+                gdb::Resume::Quit => break,
+            }
+            continue;
+        }
+
+        if paused {
+            let panel = debugger.panel(&chip8);
+            let io = chip8.io_mut();
+            io.show_panel(&panel)?;
+            let line = io.read_line(panel.len() as u16 + 1, "> ")?;
+
+            let command = if line.trim().is_empty() {
+                Ok(last_command)
+            } else {
+                Command::parse(&line)
+            };
+            match command {
+                Ok(command) => {
+                    last_command = command;
+                    let resume = debugger.command(command, &mut chip8)?;
+                    if let Command::Freeze(..) | Command::Unfreeze(_) = command {
+                        fs::write(&cheats_file, debugger.cheats().to_string())
+                            .with_context(|| format!("couldn't write {cheats_file:?}"))?;
+                        if time_travel {
+                            debugger.cheats().apply(chip8.memory_mut());
+                        }
+                    }
+                    match resume {
+                        Resume::Paused => {}
+                        Resume::Running => {
+                            paused = false;
+                            chip8.io_mut().hide_panel()?;
+                        }
+                        Resume::Quit => break,
+                    }
+                }
+                Err(e) => debugger.set_message(e),
+            }
+            continue;
+        }
+
+        if time_travel && chip8.io_mut().rewind_held() {
+            if let Some(snapshot) = rewind.step_back() {
+                chip8.load_state(snapshot)?;
+            }
+            let io = chip8.io_mut();
+            io.wait_for_frame();
+            io.update();
+            continue;
+        }
+
+        if time_travel {
+            debugger.cheats().apply(chip8.memory_mut());
+        }
+        let step = chip8.run_frame_until(|chip8| {
+            let stop = match &mut gdb {
+                Some(stub) => stub.should_stop(chip8),
+                None => debugger.should_stop(chip8),
+            };
+            // Once it's halted, there's no next instruction to record.
+            if !stop && !chip8.halted() {
+                if let Some(trace) = &mut trace {
+                    trace.record(chip8);
+                }
+                if let Some(profiler) = &mut profiler {
+                    profiler.record(chip8);
+                }
+                if let Some(coverage) = &mut coverage {
+                    coverage.record(chip8);
+                }
+            }
+            stop
+        })?;
+        match step {
+            // Stay around to look at how it ended.
+            Step::Halted if debug => {
+                debugger.pause("halted");
+                paused = true;
+                continue;
+            }
+            Step::Halted => {
+                if let Some(stub) = &mut gdb {
+                    stub.report_stop(Stop::Halted)?;
+                }
+                break;
+            }
+            Step::Stopped => {
+                match &mut gdb {
+                    Some(stub) => {
+                        stub.report_stop(Stop::Breakpoint)?;
+                        gdb_stopped = true;
+                    }
+                    None => paused = true,
+                }
+                continue;
+            }
+            Step::Executed | Step::WaitingForKey => {}
+        }
+        if time_travel {
+            rewind.push(chip8.save_state());
+        }
+        chip8.io_mut().wait_for_frame();
+
+        if let Some(stub) = &mut gdb {
+            if stub.interrupted()? {
+                stub.report_stop(Stop::Interrupted)?;
+                gdb_stopped = true;
+            }
+        }
+
+        match chip8.io_mut().take_hotkey() {
+            Some(Hotkey::SaveState) => {
+                if let Err(e) = fs::write(&state_file, chip8.save_state()) {
+                    let message = format!("couldn't write {state_file:?}: {e}");
+                    chip8.io_mut().show_panel(&[message])?;
+                }
+            }
+            Some(Hotkey::LoadState) if time_travel => {
+                // Keep running if it fails, rather than lose the game.
+                let result = fs::read(&state_file)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| Ok(chip8.load_state(&bytes)?));
+                match result {
+                    Ok(()) => {
+                        rewind.clear();
+                        // Clear any earlier error.
+                        chip8.io_mut().hide_panel()?;
+                    }
+                    Err(e) => {
+                        let message = format!("couldn't load {state_file:?}: {e}");
+                        chip8.io_mut().show_panel(&[message])?;
+                    }
+                }
+            }
+            Some(Hotkey::Break) if time_travel => match &mut gdb {
+                Some(stub) if !gdb_stopped => {
+                    stub.report_stop(Stop::Interrupted)?;
+                    gdb_stopped = true;
+                }
+                Some(_) => {}
+                None => {
+                    debugger.pause("paused");
+                    paused = true;
+                }
+            },
+            Some(Hotkey::LoadState | Hotkey::Break) | None => {}
+        }
+    }
+
+    if let (Some(trace), Some(path)) = (&mut trace, &trace_file) {
+        trace
+            .flush()
+            .with_context(|| format!("couldn't write {path:?}"))?;
+    }
+    if let (Some(coverage), Some(path)) = (&mut coverage, &coverage_file) {
+        coverage.finish(&chip8);
+        write_coverage(coverage, &rom, path).with_context(|| format!("couldn't write {path:?}"))?;
+    }
+    if let Some(profiler) = &profiler {
+        let report = profiler.report(&chip8);
+        // Put the terminal back first.
+        drop(chip8);
+        print!("\n{report}");
+    }
+
+    Ok(())
+}
+
+/// Make sure a movie is played back with the same ROM and options it was
+/// recorded with. The seed comes from the movie, so it always matches.
+fn check_movie(recorded: &movie::Header, ours: &movie::Header) -> Result<()> {
+    if recorded.rom_id != ours.rom_id {
+        bail!("it was recorded with a different ROM");
+    }
+    if recorded.quirks != ours.quirks {
+        bail!("it was recorded with --quirks {}", recorded.quirks);
+    }
+    if recorded.instructions_per_frame != ours.instructions_per_frame {
+        bail!(
+            "it was recorded with --ipf {}",
+            recorded.instructions_per_frame
+        );
+    }
+    if recorded.vip_rng != ours.vip_rng {
+        let with = if recorded.vip_rng { "with" } else { "without" };
+        bail!("it was recorded {with} --vip-rng");
+    }
+    Ok(())
+}
+
+/// Write the `--coverage` listing, with a summary at the top.
+fn write_coverage(coverage: &Coverage, rom: &[u8], path: &str) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    let (executed, data, untouched) = coverage.summary(rom.len());
+    writeln!(
+        out,
+        "; {} bytes: {executed} executed, {data} data, {untouched} untouched",
+        rom.len()
+    )?;
+    writeln!(out, "; X = executed, R = read, W = written")?;
+    for line in coverage.listing(rom) {
+        writeln!(out, "{line}")?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// Print a disassembly listing of a ROM file.
+fn disasm(args: &[String]) -> Result<()> {
+    let [path] = args else {
+        bail!("disasm: expected a single ROM file\n\n{USAGE}");
+    };
+    let rom = fs::read(path).with_context(|| format!("couldn't read {path:?}"))?;
+
+    let mut out = io::stdout().lock();
+    for line in disasm::disassemble(&rom).with_context(|| path.clone())? {
+        writeln!(out, "{line}")?;
+    }
+
+    Ok(())
+}
+
+/// Assemble a source file, and write the ROM to stdout.
+fn asm(args: &[String]) -> Result<()> {
+    let [path] = args else {
+        bail!("asm: expected a single source file\n\n{USAGE}");
+    };
+    let source = fs::read_to_string(path).with_context(|| format!("couldn't read {path:?}"))?;
+
+    let rom = asm::assemble(&source).with_context(|| path.clone())?;
+    io::stdout().write_all(&rom)?;
 
     Ok(())
 }
+
+/// Compare two traces from `--trace`, or from another emulator in the same
+/// format, and show where they first differ. Like `diff`, exits with status
+/// 1 if they do.
+fn trace_diff(args: &[String]) -> Result<()> {
+    let [a_path, b_path] = args else {
+        bail!("trace-diff: expected two trace files\n\n{USAGE}");
+    };
+    let a_text = fs::read_to_string(a_path).with_context(|| format!("couldn't read {a_path:?}"))?;
+    let b_text = fs::read_to_string(b_path).with_context(|| format!("couldn't read {b_path:?}"))?;
+    let a = trace::parse(&a_text).with_context(|| a_path.clone())?;
+    let b = trace::parse(&b_text).with_context(|| b_path.clone())?;
+    let a_lines: Vec<_> = trace::lines(&a_text).collect();
+    let b_lines: Vec<_> = trace::lines(&b_text).collect();
+
+    let mut out = io::stdout().lock();
+    let (index, shown) = match trace::first_divergence(&a, &b) {
+        None => {
+            writeln!(out, "no differences in {} instructions", a.len())?;
+            return Ok(());
+        }
+        Some(Divergence::State { index, fields }) => {
+            writeln!(
+                out,
+                "{a_path}:{} and {b_path}:{} differ before instruction {index} (cycle {}):",
+                a_lines[index].0, b_lines[index].0, a[index].cycle,
+            )?;
+            for (name, a_value, b_value) in fields {
+                writeln!(out, "    {name:<2}  {a_value} vs {b_value}")?;
+            }
+            if index == 0 {
+                writeln!(out, "so they start from different states.")?;
+            } else {
+                writeln!(out, "so the instruction before it behaved differently.")?;
+            }
+            (index, index + 1)
+        }
+        Some(Divergence::Length { len }) => {
+            let (shorter, longer) = if a.len() < b.len() {
+                (a_path, b_path)
+            } else {
+                (b_path, a_path)
+            };
+            writeln!(
+                out,
+                "{shorter} ends after {len} instructions, but {longer} keeps going"
+            )?;
+            (len, len + 1)
+        }
+    };
+
+    let start = index.saturating_sub(TRACE_DIFF_CONTEXT);
+    for (path, lines) in [(a_path, &a_lines), (b_path, &b_lines)] {
+        writeln!(out, "\n{path}:")?;
+        for (i, (n, line)) in lines.iter().enumerate().take(shown).skip(start) {
+            let marker = if i == index { '>' } else { ' ' };
+            writeln!(out, "{marker} {n:>6}: {line}")?;
+        }
+    }
+
+    out.flush()?;
+    process::exit(1);
+}
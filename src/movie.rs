@@ -0,0 +1,332 @@
+//! Input movies: every keypad change from a session, with the frame it
+//! happened on, plus the RNG seed and the options that affect execution.
+//! Playing one back with the same ROM and options reproduces the session
+//! exactly.
+//!
+//! The format is `MAGIC`, a version byte, and the `Header`: the seed,
+//! `rom_id`, and instructions per frame (big endian `u64`s), a byte that's 1
+//! for the VIP RNG, and the quirks preset name (a length byte, then ASCII).
+//! Then there's one entry per event: the number of frames since the previous
+//! event as an LEB128 varint, and a byte with the key in the low nibble and
+//! the top bit set for a press.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{self, Display},
+    io::{self, Write},
+};
+
+pub const MAGIC: &[u8; 4] = b"CH8M";
+
+/// Bump this whenever the format changes.
+pub const VERSION: u8 = 2;
+
+const PRESSED: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// Frame 0 is the first one.
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Everything besides input that playback has to match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub seed: u64,
+    /// `cheat::rom_id` of the ROM.
+    pub rom_id: u64,
+    pub instructions_per_frame: u64,
+    pub vip_rng: bool,
+    /// One of `Quirks::PRESET_NAMES`.
+    pub quirks: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub header: Header,
+    /// In order of `frame`.
+    pub events: Vec<KeyEvent>,
+}
+
+/// A movie file couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// Not a movie file.
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The data ended in the middle of the header or an event.
+    Truncated,
+    /// The quirks preset name isn't ASCII.
+    BadQuirks,
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a movie"),
+            Self::UnsupportedVersion(v) => {
+                write!(f, "unsupported movie version {v} (expected {VERSION})")
+            }
+            Self::Truncated => write!(f, "movie is truncated"),
+            Self::BadQuirks => write!(f, "movie has a bad quirks preset name"),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl Movie {
+    pub fn parse(bytes: &[u8]) -> Result<Self, MovieError> {
+        let rest = bytes.strip_prefix(MAGIC).ok_or(MovieError::BadMagic)?;
+        let (&version, rest) = rest.split_first().ok_or(MovieError::Truncated)?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let (seed, rest) = rest.split_first_chunk().ok_or(MovieError::Truncated)?;
+        let (rom_id, rest) = rest.split_first_chunk().ok_or(MovieError::Truncated)?;
+        let (ipf, rest) = rest.split_first_chunk().ok_or(MovieError::Truncated)?;
+        let (&vip_rng, rest) = rest.split_first().ok_or(MovieError::Truncated)?;
+        let (&quirks_len, rest) = rest.split_first().ok_or(MovieError::Truncated)?;
+        let (quirks, mut rest) = rest
+            .split_at_checked(quirks_len as usize)
+            .ok_or(MovieError::Truncated)?;
+        let quirks = std::str::from_utf8(quirks)
+            .ok()
+            .filter(|name| name.is_ascii())
+            .ok_or(MovieError::BadQuirks)?;
+        let header = Header {
+            seed: u64::from_be_bytes(*seed),
+            rom_id: u64::from_be_bytes(*rom_id),
+            instructions_per_frame: u64::from_be_bytes(*ipf),
+            vip_rng: vip_rng != 0,
+            quirks: quirks.to_string(),
+        };
+
+        let mut events = vec![];
+        let mut frame = 0u64;
+        while !rest.is_empty() {
+            let delta;
+            (delta, rest) = read_varint(rest).ok_or(MovieError::Truncated)?;
+            let (&byte, tail) = rest.split_first().ok_or(MovieError::Truncated)?;
+            rest = tail;
+
+            frame = frame.saturating_add(delta);
+            events.push(KeyEvent {
+                frame,
+                key: byte & 0xf,
+                pressed: byte & PRESSED != 0,
+            });
+        }
+
+        Ok(Self { header, events })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut w = MovieWriter::new(&mut bytes, &self.header).unwrap();
+        for &event in &self.events {
+            w.write(event).unwrap();
+        }
+        bytes
+    }
+}
+
+/// Writes a movie as it's recorded, so nothing is lost if the program
+/// doesn't exit cleanly.
+#[derive(Debug)]
+pub struct MovieWriter<W: Write> {
+    out: W,
+    prev_frame: u64,
+}
+
+impl<W: Write> MovieWriter<W> {
+    /// Writes the header right away.
+    pub fn new(mut out: W, header: &Header) -> io::Result<Self> {
+        assert!(header.quirks.is_ascii() && header.quirks.len() <= u8::MAX as usize);
+
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&header.seed.to_be_bytes())?;
+        out.write_all(&header.rom_id.to_be_bytes())?;
+        out.write_all(&header.instructions_per_frame.to_be_bytes())?;
+        out.write_all(&[header.vip_rng as u8, header.quirks.len() as u8])?;
+        out.write_all(header.quirks.as_bytes())?;
+        Ok(Self { out, prev_frame: 0 })
+    }
+
+    /// Events must be written in order of `frame`.
+    pub fn write(&mut self, event: KeyEvent) -> io::Result<()> {
+        assert!(event.frame >= self.prev_frame, "movie events out of order");
+        assert!(event.key <= 0xf);
+
+        let mut delta = event.frame - self.prev_frame;
+        self.prev_frame = event.frame;
+
+        // LEB128: seven bits at a time, low bits first, with the top bit set
+        // on all but the last byte.
+        let mut bytes = vec![];
+        while delta >= 0x80 {
+            bytes.push(delta as u8 | 0x80);
+            delta >>= 7;
+        }
+        bytes.push(delta as u8);
+        bytes.push(event.key | if event.pressed { PRESSED } else { 0 });
+
+        self.out.write_all(&bytes)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Returns the value and the rest of the input.
+fn read_varint(mut bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some((value, bytes));
+        }
+    }
+    None
+}
+
+/// Keypad state driven by a timeline of events instead of a keyboard, for
+/// `HeadlessIo` and movie playback.
+///
+/// The owner counts frames, and calls `advance` at the start of each one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ScriptedKeypad {
+    /// `(key, pressed)` events, by the frame they happen at.
+    timeline: BTreeMap<u64, Vec<(u8, bool)>>,
+    pressed: [bool; 16],
+    /// The last key released at the start of the current frame.
+    released: Option<u8>,
+}
+
+impl ScriptedKeypad {
+    /// `frame` is the current frame. Events for it or earlier are applied
+    /// right away, since it's too late to wait for them.
+    pub fn schedule(&mut self, frame: u64, event: KeyEvent) {
+        assert!(event.key <= 0xf);
+        let KeyEvent { key, pressed, .. } = event;
+        self.timeline
+            .entry(event.frame)
+            .or_default()
+            .push((key, pressed));
+
+        if event.frame <= frame {
+            self.apply(key, pressed);
+        }
+    }
+
+    /// Start a new frame.
+    pub fn advance(&mut self, frame: u64) {
+        self.released = None;
+
+        let events = self.timeline.get(&frame).cloned().unwrap_or_default();
+        for (key, pressed) in events {
+            self.apply(key, pressed);
+        }
+    }
+
+    pub fn is_key_pressed(&self, k: u8) -> bool {
+        self.pressed[k as usize]
+    }
+
+    pub fn key_released(&self) -> Option<u8> {
+        self.released
+    }
+
+    /// Bit `k` is set if key `k` is pressed.
+    pub fn pressed_mask(&self) -> u16 {
+        (0..16).fold(0, |acc, k| acc | (self.pressed[k] as u16) << k)
+    }
+
+    /// Restore the keys from `pressed_mask`.
+    pub fn set_pressed_mask(&mut self, mask: u16) {
+        self.released = None;
+        for (k, pressed) in self.pressed.iter_mut().enumerate() {
+            *pressed = mask & 1 << k != 0;
+        }
+    }
+
+    fn apply(&mut self, key: u8, pressed: bool) {
+        self.pressed[key as usize] = pressed;
+        if !pressed {
+            self.released = Some(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie() -> Movie {
+        let event = |frame, key, pressed| KeyEvent {
+            frame,
+            key,
+            pressed,
+        };
+        Movie {
+            header: Header {
+                seed: 0x0123_4567_89ab_cdef,
+                rom_id: 42,
+                instructions_per_frame: 15,
+                vip_rng: true,
+                quirks: "schip".to_string(),
+            },
+            events: vec![
+                event(0, 0x1, true),
+                event(0, 0x2, true),
+                event(3, 0x1, false),
+                // Needs a multi-byte varint.
+                event(1_000_000, 0xf, true),
+                event(1_000_000, 0x2, false),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let movie = movie();
+        assert_eq!(Movie::parse(&movie.to_bytes()), Ok(movie));
+    }
+
+    #[test]
+    fn truncated() {
+        let movie = movie();
+        let bytes = movie.to_bytes();
+        for len in 0..bytes.len() {
+            match Movie::parse(&bytes[..len]) {
+                // Cut off between events.
+                Ok(parsed) => {
+                    assert_eq!(parsed.header, movie.header);
+                    assert!(movie.events.starts_with(&parsed.events));
+                }
+                Err(e) => assert!(
+                    matches!(e, MovieError::Truncated | MovieError::BadMagic),
+                    "{len}: {e}"
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut bytes = movie().to_bytes();
+        bytes[4] = VERSION + 1;
+        assert_eq!(
+            Movie::parse(&bytes),
+            Err(MovieError::UnsupportedVersion(VERSION + 1))
+        );
+        assert_eq!(Movie::parse(b"CH8S"), Err(MovieError::BadMagic));
+    }
+}
@@ -0,0 +1,21 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+/// A problem on one line of a text format: assembly source, a trace, or a
+/// cheat list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Starting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
@@ -0,0 +1,194 @@
+//! Counting where a ROM spends its time, to tune `--ipf` and find busy-wait
+//! loops.
+//!
+//! Time is measured in cycles of the virtual clock (see `Chip8::cycles`).
+//! Each instruction takes one, but a `Dxyn` with the `display_wait` quirk
+//! also waits out the rest of the frame, and so does each check of an `Fx0A`
+//! that's still waiting for a key. Those waits are counted separately from
+//! running.
+
+use crate::cpu::{instruction::Instruction, io::Chip8Io, Chip8};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+};
+
+/// How many hot spots and loops the report lists.
+const REPORT_LEN: usize = 10;
+
+/// The last instruction `Profiler::record` saw.
+#[derive(Debug, Clone, Copy)]
+struct Prev {
+    pc: u16,
+    cycles: u64,
+    instr: Option<Instruction>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    /// How many times each address executed.
+    counts: Vec<u64>,
+    /// How many times each kind of instruction executed, by
+    /// `Instruction::pattern`.
+    mix: BTreeMap<&'static str, u64>,
+    /// Taken backward jumps: how many times each `(target, jump)` pair went
+    /// around.
+    loops: HashMap<(u16, u16), u64>,
+    prev: Option<Prev>,
+    running: u64,
+    vblank_wait: u64,
+    key_wait: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; 1 << 16],
+            mix: BTreeMap::new(),
+            loops: HashMap::new(),
+            prev: None,
+            running: 0,
+            vblank_wait: 0,
+            key_wait: 0,
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call before each instruction, e.g. from the `stop` callback of
+    /// `Chip8::run_frame_until`. The time since the last call is charged to
+    /// the instruction from then.
+    pub fn record<IO: Chip8Io>(&mut self, chip8: &Chip8<IO>) {
+        let pc = chip8.pc();
+        let cycles = chip8.cycles();
+        let instr = chip8.instruction_at(pc);
+
+        if let Some(prev) = self.prev {
+            // Nothing ran, e.g. we stopped in the debugger and resumed.
+            if cycles == prev.cycles {
+                return;
+            }
+            // Time went backwards, e.g. by loading a state.
+            if cycles < prev.cycles {
+                self.prev = None;
+                return self.record(chip8);
+            }
+
+            let elapsed = cycles - prev.cycles;
+            match prev.instr {
+                // Still waiting: this is another check, not a new execution.
+                Some(Instruction::WaitKey(_)) if pc == prev.pc => {
+                    self.key_wait += elapsed;
+                    self.prev = Some(Prev { cycles, ..prev });
+                    return;
+                }
+                Some(Instruction::Jump(target)) if target <= prev.pc && pc == target => {
+                    *self.loops.entry((target, prev.pc)).or_default() += 1;
+                }
+                _ => {}
+            }
+            self.running += 1;
+            self.vblank_wait += elapsed - 1;
+        }
+
+        self.counts[pc as usize] += 1;
+        let pattern = instr.map_or("????", Instruction::pattern);
+        *self.mix.entry(pattern).or_default() += 1;
+        self.prev = Some(Prev { pc, cycles, instr });
+    }
+
+    /// A summary of the run, using `chip8` to disassemble the hot spots.
+    pub fn report<IO: Chip8Io>(&self, chip8: &Chip8<IO>) -> String {
+        let mut out = String::new();
+        let executed: u64 = self.counts.iter().sum();
+        let total = self.running + self.vblank_wait + self.key_wait;
+        let ipf = chip8.instructions_per_frame() as u64;
+        let percent = |n: u64, of: u64| n as f64 * 100.0 / of.max(1) as f64;
+        let disasm = |addr: u16| match chip8.instruction_at(addr) {
+            Some(instr) => instr.to_string(),
+            None => "???".to_string(),
+        };
+
+        writeln!(
+            out,
+            "{executed} instructions in {total} cycles ({} frames at {ipf} per frame)",
+            total / ipf
+        )
+        .unwrap();
+        for (name, cycles) in [
+            ("running", self.running),
+            ("waiting for vblank", self.vblank_wait),
+            ("waiting for a key", self.key_wait),
+        ] {
+            writeln!(
+                out,
+                "    {name:<20}{cycles:>12} cycles {:>6.1}%",
+                percent(cycles, total)
+            )
+            .unwrap();
+        }
+
+        let mut loops: Vec<_> = self.loops.iter().collect();
+        loops.sort_by_key(|&(&range, &n)| (std::cmp::Reverse(n), range));
+        writeln!(out, "\nhot loops:").unwrap();
+        for (&(start, end), &iterations) in loops.iter().take(REPORT_LEN) {
+            let body = start..=end;
+            let executed_in: u64 = body.clone().map(|addr| self.counts[addr as usize]).sum();
+            let mut notes = vec![];
+            let instrs: Vec<_> = body
+                .filter(|&addr| self.counts[addr as usize] > 0)
+                .filter_map(|addr| chip8.instruction_at(addr))
+                .collect();
+            if instrs.iter().any(|i| matches!(i, Instruction::GetDelay(_))) {
+                notes.push("reads DT");
+            }
+            if instrs
+                .iter()
+                .any(|i| matches!(i, Instruction::SkipKey(_) | Instruction::SkipNotKey(_)))
+            {
+                notes.push("polls keys");
+            }
+            if !instrs.iter().any(|i| matches!(i, Instruction::Draw(..))) {
+                notes.push("doesn't draw");
+            }
+            write!(
+                out,
+                "    0x{start:03x}-0x{end:03x}{iterations:>12} times{executed_in:>12} instructions"
+            )
+            .unwrap();
+            if !notes.is_empty() {
+                write!(out, "  {}", notes.join(", ")).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+
+        let mut hot: Vec<_> = (0..=u16::MAX)
+            .filter(|&addr| self.counts[addr as usize] > 0)
+            .collect();
+        hot.sort_by_key(|&addr| std::cmp::Reverse(self.counts[addr as usize]));
+        writeln!(out, "\nhot spots:").unwrap();
+        for &addr in hot.iter().take(REPORT_LEN) {
+            let n = self.counts[addr as usize];
+            writeln!(
+                out,
+                "    0x{addr:03x}{n:>12} {:>6.1}%  {}",
+                percent(n, executed),
+                disasm(addr)
+            )
+            .unwrap();
+        }
+
+        let mut mix: Vec<_> = self.mix.iter().collect();
+        mix.sort_by_key(|&(&pattern, &n)| (std::cmp::Reverse(n), pattern));
+        writeln!(out, "\ninstruction mix:").unwrap();
+        for (pattern, &n) in mix {
+            writeln!(out, "    {pattern}{n:>12} {:>6.1}%", percent(n, executed)).unwrap();
+        }
+
+        out
+    }
+}
@@ -1,33 +1,54 @@
 mod keyboard;
-mod screen;
 
 use self::keyboard::Keyboard;
-use self::screen::Screen;
-use crate::cpu::io::{Chip8Io, DrawSprite, TIME_BETWEEN_TICKS_NS};
-use crate::cpu::screen::Point;
+use crate::cpu::io::{Chip8Io, TIME_BETWEEN_TICKS_NS};
+use crate::cpu::screen::Screen;
+use crate::movie::{KeyEvent, Movie, MovieWriter, ScriptedKeypad};
 use anyhow::Result;
 use crossterm::{
     cursor::MoveTo,
-    event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
-    style::Print,
+    event::{
+        self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
     terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::thread;
 use std::{
-    fmt::{self, Display},
-    io,
+    fs::File,
+    io::{self, BufWriter, Write},
     time::{Duration, Instant},
 };
 
+/// Emulator controls, as opposed to CHIP-8 keypad input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    SaveState,
+    LoadState,
+    /// Pause and open the debugger.
+    Break,
+}
+
 /// A `crossterm`-based implementation of `Chip8Io`.
 #[derive(Debug)]
 pub struct TerminalIo {
+    /// The most recently presented screen.
     screen: Screen,
     keyboard: Keyboard,
-    previous_tick: Instant,
-    dt: u8,
-    st: u8,
+    /// When `wait_for_frame` should return next.
+    next_frame: Instant,
+    rng: StdRng,
+    /// How many frames have finished.
+    frame: u64,
+    /// Record keypad changes here.
+    recording: Option<MovieWriter<BufWriter<File>>>,
+    /// If set, the keypad follows this, and the keyboard is only used for
+    /// hotkeys.
+    playback: Option<ScriptedKeypad>,
 }
 
 impl TerminalIo {
@@ -38,9 +59,11 @@ impl TerminalIo {
         let this = Self {
             screen: Screen::new(),
             keyboard: Keyboard::default(),
-            previous_tick: Instant::now(),
-            dt: 0,
-            st: 0,
+            next_frame: Instant::now(),
+            rng: StdRng::from_entropy(),
+            frame: 0,
+            recording: None,
+            playback: None,
         };
 
         terminal::enable_raw_mode()?;
@@ -54,82 +77,211 @@ impl TerminalIo {
         Ok(this)
     }
 
-    fn render(&self) -> Result<()> {
-        io::stdout()
-            .execute(MoveTo(0, 0))?
-            .execute(Print(DisplayScreen(&self.screen)))?;
-        Ok(())
+    /// Make `get_random_byte` reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
     }
-}
 
-/// Helper for `TerminalIo::render`.
-struct DisplayScreen<'a>(&'a Screen);
+    /// Record every keypad change to `movie`. Use the same seed as for
+    /// `set_seed`.
+    pub fn record(&mut self, movie: MovieWriter<BufWriter<File>>) {
+        self.recording = Some(movie);
+    }
 
-impl<'a> Display for DisplayScreen<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // Use Debug formatting.
-        let s = format!("{:?}", self.0);
+    /// Replay a recorded session, using its keypad changes and seed.
+    pub fn play(&mut self, movie: &Movie) {
+        self.set_seed(movie.header.seed);
 
-        // Translate \n to \r\n to work correctly with raw-mode terminal.
-        write!(f, "{}", s.replace('\n', "\r\n"))
+        let mut keypad = ScriptedKeypad::default();
+        for &event in &movie.events {
+            keypad.schedule(self.frame, event);
+        }
+        self.playback = Some(keypad);
     }
-}
 
-const TIME_BETWEEN_TICKS: Duration = Duration::from_nanos(TIME_BETWEEN_TICKS_NS);
+    /// A hotkey that was pressed during the most recent `update`, if any.
+    pub fn take_hotkey(&mut self) -> Option<Hotkey> {
+        self.keyboard.take_hotkey()
+    }
 
-impl Chip8Io for TerminalIo {
-    fn update(&mut self) {
-        self.keyboard.update().unwrap();
+    /// Is the user holding down the rewind key?
+    pub fn rewind_held(&self) -> bool {
+        self.keyboard.rewind_held()
+    }
 
-        // Perform new ticks of the delay timer and sound timer.
-        //
-        // We may end up doing multiple ticks during a single `update`; e.g., if
-        // we were blocked waiting for `blocking_get_key`, and a long time
-        // passed.
-        while self.previous_tick.elapsed() >= TIME_BETWEEN_TICKS {
-            self.dt = self.dt.saturating_sub(1);
-            self.st = self.st.saturating_sub(1);
-            self.previous_tick += TIME_BETWEEN_TICKS;
+    /// Show `lines` to the right of the screen, e.g. the debugger's panel.
+    pub fn show_panel(&mut self, lines: &[String]) -> Result<()> {
+        let mut stdout = io::stdout().lock();
+        let column = self.panel_column();
+        for (row, line) in lines.iter().enumerate() {
+            queue!(
+                stdout,
+                MoveTo(column, row as u16),
+                Print(line),
+                Clear(ClearType::UntilNewLine)
+            )?;
         }
+        queue!(
+            stdout,
+            MoveTo(column, lines.len() as u16),
+            Clear(ClearType::FromCursorDown)
+        )?;
+        stdout.flush()?;
+        Ok(())
     }
 
-    fn clear_screen(&mut self) {
-        self.screen.clear();
-        self.render().unwrap();
+    /// Remove the panel, leaving just the screen.
+    pub fn hide_panel(&mut self) -> Result<()> {
+        io::stdout().execute(Clear(ClearType::All))?;
+        self.render()
     }
 
-    fn get_random_byte(&mut self) -> u8 {
-        rand::random()
+    /// Read a line of text, echoing it after `prompt` on row `row` of the
+    /// panel. Escape gives an empty line. Panics if the user presses
+    /// `ctrl+c`, like the keyboard does.
+    pub fn read_line(&mut self, row: u16, prompt: &str) -> Result<String> {
+        let column = self.panel_column();
+        let mut line = String::new();
+        loop {
+            queue!(
+                io::stdout(),
+                MoveTo(column, row),
+                Print(prompt),
+                Print(&line),
+                Clear(ClearType::UntilNewLine)
+            )?;
+            io::stdout().flush()?;
+
+            let Event::Key(e) = event::read()? else {
+                continue;
+            };
+            if e.kind == KeyEventKind::Release {
+                continue;
+            }
+            match e.code {
+                KeyCode::Char('c') if e.modifiers.contains(KeyModifiers::CONTROL) => {
+                    panic!("control-c pressed");
+                }
+                KeyCode::Char(c) => line.push(c),
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                KeyCode::Enter => break,
+                KeyCode::Esc => {
+                    line.clear();
+                    break;
+                }
+                _ => {}
+            }
+        }
+        Ok(line)
     }
 
-    fn draw_sprite(&mut self, pos: Point, sprite: &[u8]) -> DrawSprite {
-        let collision = self.screen.draw_sprite(pos, sprite);
-        self.render().unwrap();
+    /// Leave a gap between the screen and the panel.
+    fn panel_column(&self) -> u16 {
+        self.screen.dims().x as u16 + 2
+    }
+
+    /// Draw the screen using "upper half block" characters, so each character
+    /// cell shows two pixels: the top one in the foreground color and the
+    /// bottom one in the background color.
+    fn render(&self) -> Result<()> {
+        let mut stdout = io::stdout().lock();
+        queue!(stdout, MoveTo(0, 0))?;
+
+        let dims = self.screen.dims();
+        for y in (0..dims.y).step_by(2) {
+            let mut prev = None;
+            for x in 0..dims.x {
+                let top = self.screen.pixel((x, y).into());
+                let bottom = self.screen.pixel((x, y + 1).into());
 
-        // Quirk: wait for the next tick of an imaginary "display timer" before returning.
-        sleep_until(self.previous_tick + TIME_BETWEEN_TICKS);
+                if prev != Some((top, bottom)) {
+                    queue!(
+                        stdout,
+                        SetForegroundColor(PALETTE[top as usize]),
+                        SetBackgroundColor(PALETTE[bottom as usize]),
+                    )?;
+                    prev = Some((top, bottom));
+                }
+                queue!(stdout, Print('▀'))?;
+            }
 
-        collision
+            // Use \r\n to work correctly with raw-mode terminal.
+            queue!(stdout, ResetColor, Print("\r\n"))?;
+        }
+
+        stdout.flush()?;
+        Ok(())
     }
+}
 
-    fn is_key_pressed(&mut self, k: u8) -> bool {
-        self.keyboard.is_key_pressed(k)
+/// Colors for each combination of the two XO-CHIP display planes: neither,
+/// the first, the second, and both.
+const PALETTE: [Color; 4] = [
+    Color::Black,
+    Color::White,
+    Color::DarkYellow,
+    Color::DarkRed,
+];
+
+const TIME_BETWEEN_FRAMES: Duration = Duration::from_nanos(TIME_BETWEEN_TICKS_NS);
+
+impl Chip8Io for TerminalIo {
+    fn update(&mut self) {
+        self.frame += 1;
+        self.keyboard.update().unwrap();
+
+        if let Some(keypad) = &mut self.playback {
+            keypad.advance(self.frame);
+        }
+
+        if let Some(movie) = &mut self.recording {
+            for &(key, pressed) in self.keyboard.changes() {
+                let event = KeyEvent {
+                    frame: self.frame,
+                    key,
+                    pressed,
+                };
+                movie.write(event).unwrap();
+            }
+        }
+    }
+
+    fn wait_for_frame(&mut self) {
+        sleep_until(self.next_frame);
+
+        // If we've fallen behind, e.g. because rendering is slow, don't try
+        // to catch up by rushing through frames.
+        self.next_frame = (self.next_frame + TIME_BETWEEN_FRAMES).max(Instant::now());
     }
 
-    fn blocking_get_key(&mut self) -> u8 {
-        self.keyboard.wait_for_key_release().unwrap()
+    fn present(&mut self, screen: &Screen) {
+        // The screen might have gotten smaller, so clear any leftovers.
+        if screen.dims() != self.screen.dims() {
+            io::stdout().execute(Clear(ClearType::All)).unwrap();
+        }
+
+        self.screen.clone_from(screen);
+        self.render().unwrap();
     }
 
-    fn read_delay_timer(&mut self) -> u8 {
-        self.dt
+    fn get_random_byte(&mut self) -> u8 {
+        self.rng.gen()
     }
 
-    fn write_delay_timer(&mut self, value: u8) {
-        self.dt = value;
+    fn is_key_pressed(&mut self, k: u8) -> bool {
+        match &self.playback {
+            Some(keypad) => keypad.is_key_pressed(k),
+            None => self.keyboard.is_key_pressed(k),
+        }
     }
 
-    fn write_sound_timer(&mut self, value: u8) {
-        self.st = value;
+    fn key_released(&mut self) -> Option<u8> {
+        match &self.playback {
+            Some(keypad) => keypad.key_released(),
+            None => self.keyboard.key_released(),
+        }
     }
 }
 
@@ -1,3 +1,4 @@
+use super::Hotkey;
 use anyhow::Result;
 use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
 use std::{panic, time::Duration};
@@ -5,15 +6,38 @@ use std::{panic, time::Duration};
 #[derive(Debug, Default)]
 pub struct Keyboard {
     pressed: [bool; 16],
+    /// The last key released during the most recent update.
+    released: Option<u8>,
+    /// `(key, pressed)` for each press and release during the most recent
+    /// update, ignoring repeats.
+    changes: Vec<(u8, bool)>,
+    /// The last hotkey pressed, until it's taken.
+    hotkey: Option<Hotkey>,
+    rewind_held: bool,
 }
 
 impl Keyboard {
     /// Panics if the user presses `ctrl+c`.
     pub fn update(&mut self) -> Result<()> {
+        self.released = None;
+        self.changes.clear();
+
         // Consume pending input events; update state.
         while event::poll(Duration::from_secs(0))? {
-            if let Some((k, pressed)) = filter_event(&event::read()?) {
+            let event = event::read()?;
+            if let Some(hotkey) = filter_hotkey(&event) {
+                self.hotkey = Some(hotkey);
+            } else if let Some(held) = filter_rewind(&event) {
+                self.rewind_held = held;
+            } else if let Some((k, pressed)) = filter_event(&event) {
+                if pressed != self.pressed[k as usize] || !pressed {
+                    self.changes.push((k, pressed));
+                }
                 self.pressed[k as usize] = pressed;
+
+                if !pressed {
+                    self.released = Some(k);
+                }
             }
         }
         Ok(())
@@ -24,20 +48,55 @@ impl Keyboard {
         self.pressed[x as usize]
     }
 
-    /// Block waiting for any of the 16 keys to go from pressed to released.
-    pub fn wait_for_key_release(&mut self) -> Result<u8> {
-        loop {
-            if let Some((k, pressed)) = filter_event(&event::read()?) {
-                self.pressed[k as usize] = pressed;
+    /// Did any of the 16 keys go from pressed to released during the most
+    /// recent update?
+    pub fn key_released(&self) -> Option<u8> {
+        self.released
+    }
 
-                if !pressed {
-                    return Ok(k);
-                }
-            }
-        }
+    pub fn changes(&self) -> &[(u8, bool)] {
+        &self.changes
+    }
+
+    pub fn take_hotkey(&mut self) -> Option<Hotkey> {
+        self.hotkey.take()
+    }
+
+    pub fn rewind_held(&self) -> bool {
+        self.rewind_held
+    }
+}
+
+/// `F5` saves state, `F9` loads it, and `F8` breaks into the debugger.
+fn filter_hotkey(terminal_event: &Event) -> Option<Hotkey> {
+    let Event::Key(e) = terminal_event else {
+        return None;
+    };
+    if e.kind != KeyEventKind::Press {
+        return None;
+    }
+
+    match e.code {
+        KeyCode::F(5) => Some(Hotkey::SaveState),
+        KeyCode::F(8) => Some(Hotkey::Break),
+        KeyCode::F(9) => Some(Hotkey::LoadState),
+        _ => None,
     }
 }
 
+/// If this is a press or release of `Backspace`, the rewind key, return
+/// whether it's held.
+fn filter_rewind(terminal_event: &Event) -> Option<bool> {
+    let Event::Key(e) = terminal_event else {
+        return None;
+    };
+    if e.code != KeyCode::Backspace {
+        return None;
+    }
+
+    Some(e.kind != KeyEventKind::Release)
+}
+
 /// If this is a relevant key-press/release event, return:
 /// * `(chip8_keycode, pressed)`
 fn filter_event(terminal_event: &Event) -> Option<(u8, bool)> {
@@ -57,9 +116,7 @@ fn filter_event(terminal_event: &Event) -> Option<(u8, bool)> {
         panic!("control-c pressed");
     }
 
-    let Some(k) = keycode_to_chip8(c) else {
-        return None;
-    };
+    let k = keycode_to_chip8(c)?;
 
     Some((k, pressed))
 }
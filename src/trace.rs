@@ -0,0 +1,374 @@
+//! Execution traces: one line per instruction, with the machine's state
+//! right before it runs.
+//!
+//! A line looks like this:
+//!
+//! ```text
+//! cycle=42 pc=0204 op=f233 i=0300 v=00000100000000000000000000000000 sp=0 LD B, V2
+//! ```
+//!
+//! Every field but the mnemonic is a fixed-width `key=value` pair, so traces
+//! from other emulators are easy to massage into the same format. `v` is
+//! `V0` through `VF`, and `sp` is the stack depth. For the four-byte
+//! XO-CHIP `F000 NNNN`, `op` is just `f000`.
+//!
+//! `TraceLine::parse` reads the same format back. Only `pc`, `i`, and `v`
+//! are required, the fields can come in any order, and unknown ones are
+//! ignored. Blank lines and lines starting with `#` are skipped.
+
+use crate::cpu::{instruction::Instruction, io::Chip8Io, Chip8};
+use crate::ParseError;
+use std::{
+    fmt::{self, Display},
+    io::{self, Write},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine {
+    pub cycle: u64,
+    pub pc: u16,
+    /// `None` if `pc` is out of bounds.
+    pub opcode: Option<u16>,
+    pub instr: Option<Instruction>,
+    pub i: u16,
+    pub v: [u8; 16],
+    pub sp: usize,
+}
+
+impl TraceLine {
+    /// The state before the next instruction.
+    pub fn of<IO: Chip8Io>(chip8: &Chip8<IO>) -> Self {
+        let pc = chip8.pc();
+        let opcode = chip8
+            .memory()
+            .get(pc as usize..pc as usize + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]));
+        Self {
+            cycle: chip8.cycles(),
+            pc,
+            opcode,
+            instr: chip8.instruction_at(pc),
+            i: chip8.i(),
+            v: std::array::from_fn(|x| chip8.v()[x as u8]),
+            sp: chip8.stack().len(),
+        }
+    }
+
+    /// `index` is used for `cycle` if the line doesn't have one.
+    pub fn parse(line: &str, index: usize) -> Result<Self, String> {
+        let mut cycle = None;
+        let mut pc = None;
+        let mut opcode = None;
+        let mut i = None;
+        let mut v = None;
+        let mut sp = 0;
+
+        // Everything after the `key=value` pairs is the mnemonic, which we
+        // don't need.
+        for field in line.split_whitespace() {
+            let Some((key, value)) = field.split_once('=') else {
+                break;
+            };
+            let bad = || format!("bad {key}: {value:?}");
+            match key {
+                "cycle" => cycle = Some(value.parse().map_err(|_| bad())?),
+                "pc" => pc = Some(parse_hex16(value).ok_or_else(bad)?),
+                "op" => opcode = parse_hex16(value),
+                "i" => i = Some(parse_hex16(value).ok_or_else(bad)?),
+                "v" => v = Some(parse_regs(value).ok_or_else(bad)?),
+                "sp" => sp = value.parse().map_err(|_| bad())?,
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            cycle: cycle.unwrap_or(index as u64),
+            pc: pc.ok_or("missing pc")?,
+            opcode,
+            instr: opcode.and_then(Instruction::decode),
+            i: i.ok_or("missing i")?,
+            v: v.ok_or("missing v")?,
+            sp,
+        })
+    }
+}
+
+fn parse_hex16(s: &str) -> Option<u16> {
+    u16::from_str_radix(s.strip_prefix("0x").unwrap_or(s), 16).ok()
+}
+
+/// 32 hex digits.
+fn parse_regs(s: &str) -> Option<[u8; 16]> {
+    if s.len() != 32 {
+        return None;
+    }
+    let mut v = [0; 16];
+    for (x, reg) in v.iter_mut().enumerate() {
+        *reg = u8::from_str_radix(s.get(x * 2..x * 2 + 2)?, 16).ok()?;
+    }
+    Some(v)
+}
+
+/// The lines of a trace that aren't blank or comments, with their line
+/// numbers (starting from 1).
+pub fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .map(str::trim)
+        .enumerate()
+        .map(|(n, line)| (n + 1, line))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// Parse a whole trace. The result lines up with `lines`.
+pub fn parse(text: &str) -> Result<Vec<TraceLine>, ParseError> {
+    lines(text)
+        .enumerate()
+        .map(|(index, (n, line))| {
+            TraceLine::parse(line, index).map_err(|message| ParseError { line: n, message })
+        })
+        .collect()
+}
+
+/// Where two traces first disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The state before the `index`th instruction differs, so the one before
+    /// it probably behaved differently. Each entry is a field, and the
+    /// values from each trace.
+    State {
+        index: usize,
+        fields: Vec<(String, String, String)>,
+    },
+    /// They agree until one of them ends after `len` instructions.
+    Length { len: usize },
+}
+
+/// Compare traces instruction by instruction. `cycle` isn't compared, since
+/// emulators count waiting differently; neither are `op`, `sp`, or the
+/// mnemonic.
+pub fn first_divergence(a: &[TraceLine], b: &[TraceLine]) -> Option<Divergence> {
+    for (index, (a, b)) in a.iter().zip(b).enumerate() {
+        let mut fields = vec![];
+        if a.pc != b.pc {
+            fields.push((
+                "pc".into(),
+                format!("{:04x}", a.pc),
+                format!("{:04x}", b.pc),
+            ));
+        }
+        if a.i != b.i {
+            fields.push(("i".into(), format!("{:04x}", a.i), format!("{:04x}", b.i)));
+        }
+        for x in 0..16 {
+            if a.v[x] != b.v[x] {
+                fields.push((
+                    format!("v{x:x}"),
+                    format!("{:02x}", a.v[x]),
+                    format!("{:02x}", b.v[x]),
+                ));
+            }
+        }
+
+        if !fields.is_empty() {
+            return Some(Divergence::State { index, fields });
+        }
+    }
+
+    (a.len() != b.len()).then(|| Divergence::Length {
+        len: a.len().min(b.len()),
+    })
+}
+
+impl Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cycle={} pc={:04x} op=", self.cycle, self.pc)?;
+        match self.opcode {
+            Some(opcode) => write!(f, "{opcode:04x}")?,
+            None => write!(f, "????")?,
+        }
+        write!(f, " i={:04x} v=", self.i)?;
+        for x in self.v {
+            write!(f, "{x:02x}")?;
+        }
+        write!(f, " sp={} ", self.sp)?;
+        match self.instr {
+            Some(instr) => write!(f, "{instr}"),
+            None => write!(f, "???"),
+        }
+    }
+}
+
+/// Writes a `TraceLine` for each instruction, e.g. from the `stop` callback
+/// of `Chip8::run_frame_until`. Since that can't return an error, the first
+/// one is kept for `flush`, and nothing more is written after it.
+#[derive(Debug)]
+pub struct TraceWriter<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+    /// Where the last line was an `Fx0A`, if it was.
+    waiting: Option<u16>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            error: None,
+            waiting: None,
+        }
+    }
+
+    /// Log the instruction that's about to run.
+    ///
+    /// An `Fx0A` is checked again every frame until a key comes in, but
+    /// that's still one instruction, so it only gets one line. It's done
+    /// once anything else runs.
+    pub fn record<IO: Chip8Io>(&mut self, chip8: &Chip8<IO>) {
+        let line = TraceLine::of(chip8);
+        if self.waiting == Some(line.pc) {
+            return;
+        }
+        self.waiting = matches!(line.instr, Some(Instruction::WaitKey(_))).then_some(line.pc);
+
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{line}") {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Returns the first error from `record`, if there was one.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, HeadlessIo, Quirks};
+
+    const V0: &str = "00000000000000000000000000000000";
+
+    /// A trace where `v1` counts up, one line per instruction.
+    fn counting(n: usize) -> Vec<TraceLine> {
+        let text: String = (0..n)
+            .map(|x| format!("pc={:04x} i=0000 v=00{x:02x}{}\n", 0x200 + 2 * x, &V0[4..]))
+            .collect();
+        parse(&text).unwrap()
+    }
+
+    #[test]
+    fn identical() {
+        let a = counting(5);
+        assert_eq!(first_divergence(&a, &a.clone()), None);
+
+        // Only pc, i, and v count.
+        let mut b = a.clone();
+        b[2].cycle = 99;
+        b[2].sp = 3;
+        b[2].opcode = None;
+        assert_eq!(first_divergence(&a, &b), None);
+    }
+
+    #[test]
+    fn diverges_in_the_middle() {
+        let a = counting(5);
+        let mut b = a.clone();
+        b[3].v[1] = 0x7f;
+        b[3].i = 0x300;
+        b[4].pc = 0;
+        assert_eq!(
+            first_divergence(&a, &b),
+            Some(Divergence::State {
+                index: 3,
+                fields: vec![
+                    ("i".into(), "0000".into(), "0300".into()),
+                    ("v1".into(), "03".into(), "7f".into()),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn one_is_a_prefix() {
+        let a = counting(5);
+        let b = counting(3);
+        assert_eq!(
+            first_divergence(&a, &b),
+            Some(Divergence::Length { len: 3 })
+        );
+        assert_eq!(
+            first_divergence(&b, &a),
+            Some(Divergence::Length { len: 3 })
+        );
+        assert_eq!(
+            first_divergence(&a, &[]),
+            Some(Divergence::Length { len: 0 })
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let good = format!("pc=0200 i=0000 v={V0}");
+        let text = format!("# comment\n\n{good}\n  {good} ADD V0, 1\n");
+        let lines = parse(&text).unwrap();
+        assert_eq!(lines.len(), 2);
+        // Lines without a cycle are numbered from 0, skipping comments.
+        assert_eq!(lines[1].cycle, 1);
+
+        for (line, message) in [
+            ("pc=0200 v=00".to_string(), "bad v: \"00\""),
+            (format!("pc=10000 i=0000 v={V0}"), "bad pc: \"10000\""),
+            (format!("pc=0200 i=xyz v={V0}"), "bad i: \"xyz\""),
+            (
+                format!("cycle=-1 pc=0200 i=0000 v={V0}"),
+                "bad cycle: \"-1\"",
+            ),
+            (format!("pc=0200 i=0000 v={V0} sp=x"), "bad sp: \"x\""),
+            (format!("i=0000 v={V0}"), "missing pc"),
+            (format!("pc=0200 v={V0}"), "missing i"),
+            ("pc=0200 i=0000".to_string(), "missing v"),
+            // The mnemonic starts at the first field without `=`.
+            (format!("pc=0200 LD i=0000 v={V0}"), "missing i"),
+        ] {
+            let text = format!("{good}\n# comment\n{line}\n");
+            let expected = ParseError {
+                line: 3,
+                message: message.to_string(),
+            };
+            assert_eq!(parse(&text), Err(expected), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn one_line_per_key_wait() {
+        // The `Dxyn` waits for vblank, so each key is only seen once.
+        let rom = asm::assemble("loop: LD V0, K\nDRW V1, V1, 1\nJP loop").unwrap();
+        let mut io = HeadlessIo::new(0);
+        io.tap_key(8, 0x5, 1);
+        io.tap_key(20, 0x6, 1);
+        let mut chip8 = Chip8::new(&rom, Quirks::default(), io).unwrap();
+
+        let mut trace = TraceWriter::new(vec![]);
+        for _ in 0..30 {
+            chip8
+                .run_frame_until(|chip8| {
+                    trace.record(chip8);
+                    false
+                })
+                .unwrap();
+        }
+        trace.flush().unwrap();
+
+        let text = String::from_utf8(trace.out).unwrap();
+        let lines = parse(&text).unwrap();
+        let pcs: Vec<u16> = lines.iter().map(|line| line.pc).collect();
+        assert_eq!(pcs, [0x200, 0x202, 0x204, 0x200, 0x202, 0x204, 0x200]);
+        assert_eq!(lines[1].v[0], 0x5);
+        assert_eq!(lines[4].v[0], 0x6);
+    }
+}
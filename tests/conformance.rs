@@ -0,0 +1,148 @@
+//! Run test ROMs headlessly until they halt, and compare the final screen
+//! against a snapshot in `tests/snapshots`, in the same format as `Screen`'s
+//! `Debug` output.
+//!
+//! ROMs live in `tests/roms`, either as binaries (`.ch8`) or as source for
+//! `chip_8::asm` (`.asm`). The Timendus test suite isn't checked in; copy
+//! its ROMs into `tests/roms` to run those cases too.
+//!
+//! Set `UPDATE_SNAPSHOTS=1` to write the snapshots instead of checking them.
+
+use chip_8::{asm, Chip8, HeadlessIo, Quirks, Step};
+use std::{env, fs, path::Path};
+
+/// Give up on ROMs that haven't halted after this long.
+const MAX_FRAMES: u64 = 60 * 60;
+
+struct Case {
+    rom: &'static str,
+    preset: &'static str,
+    /// Timendus ROMs read their menu choice from here, if it's set.
+    platform: Option<u8>,
+    /// `(frame, key)` pairs; each key is held for a few frames.
+    taps: &'static [(u64, u8)],
+    /// Skip the case if the ROM isn't there, instead of failing.
+    optional: bool,
+}
+
+impl Case {
+    const fn new(rom: &'static str, preset: &'static str) -> Self {
+        Self {
+            rom,
+            preset,
+            platform: None,
+            taps: &[],
+            optional: false,
+        }
+    }
+
+    const fn timendus(rom: &'static str, preset: &'static str, platform: Option<u8>) -> Self {
+        Self {
+            platform,
+            optional: true,
+            ..Self::new(rom, preset)
+        }
+    }
+
+    fn name(&self) -> String {
+        let stem = Path::new(self.rom).file_stem().unwrap().to_str().unwrap();
+        format!("{stem}-{}", self.preset)
+    }
+}
+
+const CASES: &[Case] = &[
+    Case::new("flags.asm", "vip"),
+    Case::new("flags.asm", "schip"),
+    Case::new("quirks.asm", "vip"),
+    Case::new("quirks.asm", "schip"),
+    Case::new("quirks.asm", "xochip"),
+    Case::new("hires.asm", "schip"),
+    Case {
+        taps: &[(10, 0x5), (30, 0x7), (50, 0xa)],
+        ..Case::new("keypad.asm", "vip")
+    },
+    Case {
+        taps: &[(10, 0x5), (30, 0x7), (50, 0xa)],
+        ..Case::new("keypad.asm", "schip")
+    },
+    Case::timendus("1-chip8-logo.ch8", "vip", None),
+    Case::timendus("2-ibm-logo.ch8", "vip", None),
+    Case::timendus("3-corax+.ch8", "vip", None),
+    Case::timendus("4-flags.ch8", "vip", None),
+    Case::timendus("5-quirks.ch8", "vip", Some(1)),
+    Case::timendus("5-quirks.ch8", "schip", Some(2)),
+    Case::timendus("5-quirks.ch8", "xochip", Some(3)),
+    Case {
+        // `Fx0A`, then press and release 5.
+        taps: &[(30, 0x5)],
+        ..Case::timendus("6-keypad.ch8", "vip", Some(3))
+    },
+];
+
+#[test]
+fn conformance() {
+    let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+
+    let mut failures = vec![];
+    for case in CASES {
+        let name = case.name();
+        let rom_path = dir.join("roms").join(case.rom);
+        if case.optional && !rom_path.exists() {
+            eprintln!("skipping {name}: {rom_path:?} not found");
+            continue;
+        }
+
+        let screen = match run(case, &rom_path) {
+            Ok(screen) => screen,
+            Err(e) => {
+                failures.push(format!("{name}: {e}"));
+                continue;
+            }
+        };
+
+        let snapshot_path = dir.join("snapshots").join(format!("{name}.txt"));
+        if update {
+            fs::write(&snapshot_path, &screen).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&snapshot_path) {
+            Ok(expected) if expected == screen => {}
+            Ok(expected) => failures.push(format!(
+                "{name}: screen doesn't match {snapshot_path:?}\n\
+                 expected:{expected}\nactual:{screen}"
+            )),
+            Err(e) => failures.push(format!("{name}: couldn't read {snapshot_path:?}: {e}")),
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+/// Run a case to the end, and return the screen in `Debug` format.
+fn run(case: &Case, rom_path: &Path) -> Result<String, String> {
+    let rom = if case.rom.ends_with(".asm") {
+        let source = fs::read_to_string(rom_path).map_err(|e| e.to_string())?;
+        asm::assemble(&source).map_err(|e| e.to_string())?
+    } else {
+        fs::read(rom_path).map_err(|e| e.to_string())?
+    };
+    let quirks = Quirks::preset(case.preset).ok_or("unknown preset")?;
+
+    let mut io = HeadlessIo::new(0);
+    for &(frame, key) in case.taps {
+        io.tap_key(frame, key, 5);
+    }
+
+    let mut chip8 = Chip8::new(&rom, quirks, io).map_err(|e| e.to_string())?;
+    if let Some(platform) = case.platform {
+        chip8.memory_mut()[0x1ff] = platform;
+    }
+
+    for _ in 0..MAX_FRAMES {
+        if chip8.run_frame().map_err(|e| e.to_string())? == Step::Halted {
+            return Ok(format!("{:?}", chip8.io().screen()));
+        }
+    }
+    Err(format!("didn't halt after {MAX_FRAMES} frames"))
+}
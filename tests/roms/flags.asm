@@ -0,0 +1,107 @@
+; Carry, borrow, and shift flags.
+;
+; Each test shows two digits: the low nibble of the result, and then VF.
+; Five tests per row:
+;
+;   ADD carry     ADD no carry  SUB no borrow SUB borrow    SUB equal
+;   SUBN          SUBN borrow   SHR           SHL           SHL no carry
+;   ADD to VF     SUB from VF   OR            AND           XOR
+;
+; The last three depend on the `logic_resets_vf` quirk, since VF starts at 7.
+
+        LD V3, 1
+        LD V4, 1
+
+        LD V0, 0xff
+        LD V1, 0x01
+        ADD V0, V1
+        CALL show
+
+        LD V0, 0x10
+        LD V1, 0x01
+        ADD V0, V1
+        CALL show
+
+        LD V0, 5
+        LD V1, 3
+        SUB V0, V1
+        CALL show
+
+        LD V0, 3
+        LD V1, 5
+        SUB V0, V1
+        CALL show
+
+        LD V0, 5
+        LD V1, 5
+        SUB V0, V1
+        CALL show
+
+        LD V0, 3
+        LD V1, 5
+        SUBN V0, V1
+        CALL show
+
+        LD V0, 5
+        LD V1, 3
+        SUBN V0, V1
+        CALL show
+
+        LD V0, 0x03
+        SHR V0
+        CALL show
+
+        LD V0, 0x81
+        SHL V0
+        CALL show
+
+        LD V0, 0x41
+        SHL V0
+        CALL show
+
+        ; The flag wins over the result.
+        LD VF, 0xff
+        LD V1, 1
+        ADD VF, V1
+        LD V0, VF
+        CALL show
+
+        LD VF, 5
+        LD V1, 3
+        SUB VF, V1
+        LD V0, VF
+        CALL show
+
+        LD V0, 2
+        LD V1, 1
+        LD VF, 7
+        OR V0, V1
+        CALL show
+
+        LD V0, 3
+        LD V1, 6
+        LD VF, 7
+        AND V0, V1
+        CALL show
+
+        LD V0, 3
+        LD V1, 6
+        LD VF, 7
+        XOR V0, V1
+        CALL show
+
+end:    JP end
+
+; Draw V0 and VF at (V3, V4), and move along.
+show:   LD V5, VF
+        LD F, V0
+        DRW V3, V4, 5
+        ADD V3, 5
+        LD F, V5
+        DRW V3, V4, 5
+        ADD V3, 7
+        SE V3, 61
+        RET
+        LD V3, 1
+        ADD V4, 6
+        RET
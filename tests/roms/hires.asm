@@ -0,0 +1,29 @@
+; SUPER-CHIP high resolution mode: big digits, a 16x16 sprite, and
+; scrolling.
+
+        HIGH
+
+        LD V0, 0
+        LD V1, 0
+        LD V2, 0xa
+        LD HF, V2
+        DRW V0, V1, 10
+
+        LD V0, 12
+        LD I, ring
+        DRW V0, V1, 0
+
+        ; Everything drawn so far moves down 4 and right 4.
+        SCD 4
+        SCR
+
+        LD V0, 120
+        LD V1, 56
+        LD V2, 8
+        LD F, V2
+        DRW V0, V1, 5
+
+end:    JP end
+
+ring:   :word 0x07e0, 0x1818, 0x2004, 0x4002, 0x4002, 0x8001, 0x8001, 0x8001
+        :word 0x8001, 0x8001, 0x8001, 0x4002, 0x4002, 0x2004, 0x1818, 0x07e0
@@ -0,0 +1,44 @@
+; Waiting for keys with Fx0A, and polling them with Ex9E and ExA1.
+;
+; The test taps 5, then 7, then A. Each step draws a digit once it sees the
+; key, so a missing digit shows where it got stuck:
+;
+;   5   Fx0A got the key that was pressed and released
+;   7   Ex9E saw 7 held down
+;   0   ExA1 saw 7 let go
+;   A   Fx0A again, after polling
+
+        LD V3, 1
+        LD V4, 1
+
+        LD V0, K
+        CALL show
+
+        LD V0, 7
+held:   SKP V0
+        JP held
+        CALL show
+
+released:
+        SKNP V0
+        JP released
+        LD V0, 0
+        CALL show
+
+        ; Wait for the next frame, or Fx0A would see 7 being let go.
+        LD V1, 1
+        LD DT, V1
+wait:   LD V1, DT
+        SE V1, 0
+        JP wait
+
+        LD V0, K
+        CALL show
+
+end:    JP end
+
+; Draw the digit in V0 at (V3, V4), and move along.
+show:   LD F, V0
+        DRW V3, V4, 5
+        ADD V3, 6
+        RET
@@ -0,0 +1,51 @@
+; Instructions that behave differently depending on the quirks.
+;
+; One digit each:
+;   `Fx65` increments I: 3 if it does, 1 if not
+;   `8xy6` shifts Vy: 2 if it does, 0 if not
+;   `Bnnn` adds V0: 1 if it does, 2 if it adds Vx
+;
+; Then a sprite drawn across the bottom right corner, to show whether it's
+; clipped or wraps around.
+
+        LD V3, 8
+        LD V4, 8
+
+        LD I, table
+        LD V1, [I]
+        LD V0, [I]
+        CALL show
+
+        LD V0, 1
+        LD V1, 4
+        SHR V0, V1
+        CALL show
+
+        LD V0, 0
+        LD V2, 2
+        JP V0, 0x280
+jumped: CALL show
+
+        LD V0, 60
+        LD V1, 28
+        LD I, box
+        DRW V0, V1, 8
+
+end:    JP end
+
+; Draw V0 at (V3, V4), and move along.
+show:   LD F, V0
+        DRW V3, V4, 5
+        ADD V3, 5
+        RET
+
+table:  :byte 1, 2, 3
+box:    :byte 0xff, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xff
+
+:org 0x280
+        JP one
+        JP two
+one:    LD V0, 1
+        JP jumped
+two:    LD V0, 2
+        JP jumped